@@ -16,7 +16,8 @@ fn main() {
                         ..default()
                     },
                     ..default()
-                }
+                },
+                ..default()
             },
         ))
         .add_systems(Startup, setup)
@@ -26,7 +26,8 @@ fn main() {
                         ..default()
                     },
                     ..default()
-                }
+                },
+                ..default()
             },
             LogDiagnosticsPlugin::default(),
             FrameTimeDiagnosticsPlugin,
@@ -1,21 +1,58 @@
-#import bevy_pbr::mesh_functions::mesh_position_local_to_clip
-#import bevy_pbr::mesh_bindings::mesh
-#import bevy_pbr::mesh_view_bindings::globals
-#import bevy_pbr::mesh_view_bindings::lights
 #import bevy_pbr::mesh_view_bindings::view
 #import bevy_pbr::utils::PI
 #import bevy_pbr::utils::random1D
+#import bevy_pbr::utils::octahedral_decode
+#import bevy_procedural_grass::wind::{Wind, wind, previous_wind, zoned_wind_sway, gust_sway, impulse_push}
+
+#ifdef PREPASS_PIPELINE
+#import bevy_render::globals::Globals
+#import bevy_pbr::prepass_bindings
+#ifdef GRASS_PBR
+#import bevy_pbr::mesh_types
+#import bevy_pbr::pbr_types
+#import bevy_pbr::pbr_functions
+#import bevy_pbr::pbr_deferred_types
+#import bevy_pbr::rgb9e5
+#import bevy_pbr::utils::octahedral_encode
+#endif
+#else
+#import bevy_pbr::mesh_view_bindings::globals
+#import bevy_pbr::mesh_view_bindings::lights
+#import bevy_pbr::mesh_view_bindings::point_lights
+#import bevy_pbr::mesh_view_bindings::fog
+#import bevy_pbr::mesh_view_types
+#import bevy_pbr::mesh_types
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
 #import bevy_pbr::pbr_types
 #import bevy_pbr::pbr_functions
 #import bevy_pbr::shadows
+#endif
+
+#ifdef PREPASS_PIPELINE
+// the prepass view layout only has view, globals and the previous view projection
+@group(0) @binding(1)
+var<uniform> globals: Globals;
+#endif
 
 struct Vertex {
     @location(0) position: vec3<f32>,
     @location(2) uv: vec2<f32>,
 
-    @location(3) i_pos: vec3<f32>,
-    @location(4) i_normal: vec3<f32>,
-    @location(5) i_chunk_uvw: vec3<f32>,
+    // quantized GrassData, see decode_instance
+    @location(3) i_position: vec4<u32>,
+    @location(4) i_chunk: vec4<i32>,
+    @location(5) i_packed: u32,
+};
+
+struct Instance {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    chunk_uvw: vec3<f32>,
+    seed: f32,
+    height_scale: f32,
+    blade_type: u32,
+    tint: vec3<f32>,
 };
 
 struct Color {
@@ -23,8 +60,9 @@ struct Color {
     color_1: vec4<f32>,
     color_2: vec4<f32>,
 };
+// one color and blade per grass variant, indexed by the blade type of the instance
 @group(2) @binding(0)
-var<uniform> color: Color;
+var<uniform> colors: array<Color, #{MAX_GRASS_VARIANTS}u>;
 
 struct Blade {
     length: f32,
@@ -35,24 +73,88 @@ struct Blade {
     p2_flexibility: f32,
     curve: f32,
     specular: f32,
+    roughness: f32,
+    reflectance: f32,
+    translucency: f32,
+    thickness: f32,
+    length_variance: f32,
+    width_variance: f32,
+    flexibility_variance: f32,
+    curve_variance: f32,
+    hue_variance: f32,
+    brightness_variance: f32,
+    facing: u32,
+    facing_direction: f32,
 }
 @group(2) @binding(1)
-var<uniform> blade: Blade;
-
-struct Wind {
-    speed: f32,
-    amplitude: f32,
-    frequency: f32,
-    direction: f32,
-    oscillation: f32,
-    scale: f32,
-    _padding: vec2<f32>,
-};
-@group(3) @binding(0)
-var<uniform> wind: Wind;
+var<uniform> blades: array<Blade, #{MAX_GRASS_VARIANTS}u>;
+
+const FACING_DIRECTION: u32 = 1u;
+const FACING_CAMERA: u32 = 2u;
 
-@group(3) @binding(1)
-var t_wind_map: texture_2d<f32>;
+struct Chunks {
+    size: f32,
+    // the first camera, FACING_CAMERA blades turn towards it in the shadow and prepass views too
+    camera_position: vec3<f32>,
+};
+@group(2) @binding(2)
+var<uniform> chunks: Chunks;
+
+struct Season {
+    value: f32,
+    variance: f32,
+    strength: f32,
+    _padding: f32,
+};
+@group(2) @binding(3)
+var<uniform> season: Season;
+@group(2) @binding(4)
+var season_ramp: texture_2d<f32>;
+@group(2) @binding(5)
+var season_sampler: sampler;
+
+struct Coverage {
+    color: vec4<f32>,
+    mask_min: vec2<f32>,
+    mask_max: vec2<f32>,
+    amount: f32,
+    reach: f32,
+    flatten: f32,
+    masked: f32,
+};
+@group(2) @binding(6)
+var<uniform> coverage: Coverage;
+@group(2) @binding(7)
+var coverage_mask: texture_2d<f32>;
+@group(2) @binding(8)
+var coverage_sampler: sampler;
+
+#ifdef STANDARD_GRASS_MATERIAL
+struct StandardGrassMaterial {
+    alpha_cutoff: f32,
+    texture_columns: u32,
+};
+@group(1) @binding(0)
+var<uniform> material: StandardGrassMaterial;
+#ifdef GRASS_ALBEDO_TEXTURE
+@group(1) @binding(1)
+var albedo_texture: texture_2d<f32>;
+@group(1) @binding(2)
+var albedo_sampler: sampler;
+#endif
+#ifdef GRASS_NORMAL_TEXTURE
+@group(1) @binding(3)
+var normal_texture: texture_2d<f32>;
+@group(1) @binding(4)
+var normal_sampler: sampler;
+#endif
+#ifdef GRASS_ALPHA_TEXTURE
+@group(1) @binding(5)
+var alpha_texture: texture_2d<f32>;
+@group(1) @binding(6)
+var alpha_sampler: sampler;
+#endif
+#endif
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
@@ -61,48 +163,123 @@ struct VertexOutput {
     @location(3) world_position: vec3<f32>,
     @location(4) world_normal: vec3<f32>,
     @location(5) bezier_tangent: vec3<f32>,
+#ifdef DEPTH_CLAMP_ORTHO
+    @location(6) clip_position_unclamped: vec4<f32>,
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    @location(7) previous_world_position: vec3<f32>,
+#endif
+#ifdef STANDARD_GRASS_MATERIAL
+    @location(8) texture_uv: vec2<f32>,
+#endif
+    @location(9) tint: vec3<f32>,
+    @location(10) @interpolate(flat) blade_type: u32,
+    // drives the per-blade variation of the fragment shader
+    @location(11) @interpolate(flat) seed: f32,
+    // how covered the blade is, sampled at its root
+    @location(12) @interpolate(flat) cover: f32,
 };
 
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
 
-    let uv = vertex.uv;
+    let instance = decode_instance(vertex);
+    let cover = blade_cover(instance.position);
+    let blade_vertex = deform_blade(vertex, instance, wind, globals.time, cover);
+
+    // the blades are placed in world space from the instance data, group 1 is left to the grass material
+    out.clip_position = view.view_proj * vec4<f32>(blade_vertex.position, 1.0);
+#ifdef DEPTH_CLAMP_ORTHO
+    out.clip_position_unclamped = out.clip_position;
+    out.clip_position.z = min(out.clip_position.z, 1.0);
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    // the same blade at the previous frame's time and wind, so the sway shows up in the motion vectors
+    out.previous_world_position = deform_blade(vertex, instance, previous_wind, globals.time - globals.delta_time, cover).position;
+#endif
+
+    out.uv = vertex.uv;
+    out.normal = blade_vertex.normal;
+    out.world_position = blade_vertex.position;
+    out.world_normal = instance.normal;
+    out.bezier_tangent = blade_vertex.tangent;
+    out.tint = instance.tint;
+    out.blade_type = instance.blade_type;
+    out.seed = instance.seed;
+    out.cover = cover;
+
+#ifdef STANDARD_GRASS_MATERIAL
+    // each blade samples one of the texture columns, with the tip at the top of the texture
+    let columns = f32(max(material.texture_columns, 1u));
+    let column = min(floor(instance.seed * columns), columns - 1.0);
+    out.texture_uv = vec2<f32>((vertex.uv.x + column) / columns, 1.0 - vertex.uv.y);
+#endif
 
-    var hash_id = random1D(vertex.i_pos.x * 100. + vertex.i_pos.y * 100. + vertex.i_pos.z * 0.05 + 2.);
-    hash_id = random1D(hash_id * 100000.);
+    return out;
+}
 
-    var position = vertex.position;
+fn decode_instance(vertex: Vertex) -> Instance {
+    var out: Instance;
+
+    out.chunk_uvw = vec3<f32>(vertex.i_position.xyz) / 65535.0;
+    out.position = (vec3<f32>(vertex.i_chunk.xyz) + out.chunk_uvw) * chunks.size;
+    let normal = vec2<u32>(vertex.i_position.w & 0xffu, vertex.i_position.w >> 8u);
+    out.normal = octahedral_decode(vec2<f32>(normal) / 255.0);
+    out.seed = f32(vertex.i_packed & 0xffffu) / 65535.0;
+    out.height_scale = f32((vertex.i_packed >> 16u) & 0xffu) / 255.0;
+    out.blade_type = vertex.i_packed >> 24u;
+    // sRGB 5:6:5 color map tint
+    let tint = u32(vertex.i_chunk.w) & 0xffffu;
+    let tint_srgb = vec3<f32>(f32(tint >> 11u) / 31.0, f32((tint >> 5u) & 0x3fu) / 63.0, f32(tint & 0x1fu) / 31.0);
+    out.tint = pow(tint_srgb, vec3<f32>(2.2));
+
+    return out;
+}
 
-    let rad = wind.direction * PI / 180.0;
-    let wind_direction = vec2<f32>(cos(rad), sin(rad));
-    var facing = normalize(vec2<f32>(mix(-1., 1., hash_id), mix(-1., 1., random1D(hash_id * vertex.i_pos.x))));
+struct BladeVertex {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    tangent: vec3<f32>,
+};
 
-    let random_point = vec2<f32>(fract(vertex.i_pos.x * 0.1 * hash_id), fract(vertex.i_pos.y * 0.1 * hash_id));
-    let r = sample_wind_map(random_point, wind.speed).r;
-    
-    var wind_pos = fract(vec2<f32>(vertex.i_pos.x, vertex.i_pos.z) / wind.scale);
-    let sample = sample_wind_map(wind_pos, wind.speed).rgb;
-    let t = unpack_float(sample);
+fn deform_blade(vertex: Vertex, instance: Instance, wind: Wind, time: f32, cover: f32) -> BladeVertex {
+    var out: BladeVertex;
 
-    let blade_length = mix(blade.length, blade.length + blade.length / 2., fract(hash_id));
+    let blade = blades[instance.blade_type];
+    let uv = vertex.uv;
+
+    let hash_id = instance.seed;
+
+    var position = vertex.position;
 
-    let theta = 2.0 * PI * random1D(hash_id);
+    let blade_length = mix(blade.length, blade.length * (1.0 + blade.length_variance), instance.height_scale);
+
+    // the blade leans towards theta, turning its flat side that way
+    var theta = 2.0 * PI * random1D(hash_id);
+    if (blade.facing == FACING_DIRECTION) {
+        theta = blade.facing_direction * PI / 180.0;
+    } else if (blade.facing == FACING_CAMERA) {
+        let to_camera = chunks.camera_position.xz - instance.position.xz;
+        theta = atan2(to_camera.y, to_camera.x);
+    }
     let radius = blade_length * mix(blade.tilt - blade.tilt_variance, blade.tilt, fract(hash_id * 123.));
     var xz = radius * vec2<f32>(cos(theta), sin(theta)); 
     let base_p3 = vec3<f32>(xz.x, sqrt(blade_length * blade_length - dot(xz, xz)), xz.y);
     let base_normal = normalize(vec2<f32>(-base_p3.z, base_p3.x));
 
-    //let xz_displacement = sample_displacement_image(vertex.i_chunk_uvw.xz);
+    //let xz_displacement = sample_displacement_image(instance.chunk_uvw.xz);
 
     //let angle = xz_displacement.r * 2.0 * PI;
     //let displace_direction = vec2<f32>(-cos(angle), -sin(angle));
-    //var displace_strength = xz_displacement.a * (1.0 - clamp(abs(xz_displacement.b - vertex.i_chunk_uvw.y) / (length / 30.0), 0.0, 1.0));
+    //var displace_strength = xz_displacement.a * (1.0 - clamp(abs(xz_displacement.b - instance.chunk_uvw.y) / (length / 30.0), 0.0, 1.0));
     
     //xz += displace_direction * (length + blade.tilt) * displace_strength;
 
-    xz += -wind_direction * (0.5 * (sin(t * wind.frequency))) * wind.amplitude;
-    xz += base_normal * sin(r * 0.2) * wind.oscillation;
+    xz += zoned_wind_sway(wind, instance.position, instance.seed, base_normal, time)
+        + gust_sway(instance.position, time - globals.time)
+        + impulse_push(instance.position);
 
     var y = max(-pow((length(xz) * 0.5), 2.) + blade_length, 0.01);
     var p3 = vec3<f32>(xz.x, y, xz.y);
@@ -116,53 +293,128 @@ fn vertex(vertex: Vertex) -> VertexOutput {
 
     let distance = distance(base_p3, p3);
 
-    p1 += blade_normal * (y - blade_length) * mix(blade.p1_flexibility, blade.p1_flexibility + 0.2, fract(hash_id * 99.));
-    p2 += blade_normal * (y - blade_length) * mix(blade.p2_flexibility, blade.p2_flexibility + 0.2, fract(hash_id * 2480.));
+    p1 += blade_normal * (y - blade_length) * mix(blade.p1_flexibility, blade.p1_flexibility + blade.flexibility_variance, fract(hash_id * 99.));
+    p2 += blade_normal * (y - blade_length) * mix(blade.p2_flexibility, blade.p2_flexibility + blade.flexibility_variance, fract(hash_id * 2480.));
 
-    let bezier = cubic_bezier(uv.y, p0, p1, p2, p3);
-    let tangent = bezier_tangent(uv.y, p0, p1, p2, p3);
+    // the weight of the cover presses the blade down
+    let squash = vec3<f32>(1.0, 1.0 - coverage.flatten * cover, 1.0);
+    let bezier = cubic_bezier(uv.y, p0, p1, p2, p3) * squash;
+    let tangent = bezier_tangent(uv.y, p0, p1, p2, p3) * squash;
     position.y = bezier.y;
-    let width = blade.width * (1.0 - pow(uv.y, 2.));
+    let width = mix(blade.width, blade.width * (1.0 + blade.width_variance), fract(hash_id * 7919.)) * (1.0 - pow(uv.y, 2.));
     let xz_pos = bezier.xz + (base_normal * vertex.position.x * width);
     position.x = xz_pos.x;
     position.z = xz_pos.y;
 
-    let rotation_matrix = rotate_align(vec3<f32>(0.0, 1.0, 0.0), vertex.i_normal);
+    let rotation_matrix = rotate_align(vec3<f32>(0.0, 1.0, 0.0), instance.normal);
     position = rotation_matrix * position;
 
     var normal = normalize(cross(tangent, vec3<f32>(blade_dir_normal.x, 0.0, blade_dir_normal.y)));
     normal = rotation_matrix * normal;
     out.normal = normal;
     
-    position += vertex.i_pos.xyz;
-
-    out.clip_position = mesh_position_local_to_clip(
-        identity_matrix, 
-        vec4<f32>(position, 1.0)
-    );
+    position += instance.position;
 
-    out.uv = uv;
-    out.world_position = position;
-    out.world_normal = vertex.i_normal;
-    out.bezier_tangent = tangent;
+    out.position = position;
+    out.tangent = tangent;
 
     return out;
 }
 
+#ifdef PREPASS_PIPELINE
+#ifdef PREPASS_FRAGMENT
+struct FragmentOutput {
+#ifdef NORMAL_PREPASS
+    @location(0) normal: vec4<f32>,
+#endif
+#ifdef MOTION_VECTOR_PREPASS
+    @location(1) motion_vector: vec2<f32>,
+#endif
+#ifdef DEFERRED_PREPASS
+    @location(2) deferred: vec4<u32>,
+    @location(3) deferred_lighting_pass_id: u32,
+#endif
+#ifdef DEPTH_CLAMP_ORTHO
+    @builtin(frag_depth) frag_depth: f32,
+#endif
+};
+
 @fragment
-fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> @location(0) vec4<f32> {
-    var normal = in.normal;
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var out: FragmentOutput;
 
-    let uv_x_transformed = in.uv.x * 2.0 - 1.0;
-    var normal_curve = blade.curve * -1.;
+    let albedo = blade_albedo(in);
 
-    if (!is_front) {
-        normal = -normal;
-        normal_curve = blade.curve;
-    }
-    normal = normalize(rotate_vector(normal, in.bezier_tangent, normal_curve * uv_x_transformed));
+#ifdef NORMAL_PREPASS
+    out.normal = vec4<f32>(shading_normal(in, is_front) * 0.5 + vec3<f32>(0.5), 1.0);
+#endif
+
+#ifdef MOTION_VECTOR_PREPASS
+    let clip_position_t = view.unjittered_view_proj * vec4<f32>(in.world_position, 1.0);
+    let clip_position = clip_position_t.xy / clip_position_t.w;
+    let previous_clip_position_t = prepass_bindings::previous_view_proj * vec4<f32>(in.previous_world_position, 1.0);
+    let previous_clip_position = previous_clip_position_t.xy / previous_clip_position_t.w;
+    out.motion_vector = (clip_position - previous_clip_position) * vec2<f32>(0.5, -0.5);
+#endif
+
+#ifdef DEFERRED_PREPASS
+    let pbr_input = blade_pbr_input(in, shading_normal(in, is_front), albedo);
+    out.deferred = blade_gbuffer(pbr_input);
+    out.deferred_lighting_pass_id = 1u;
+#endif
 
-    let base_color_gradient = mix(color.color_1, color.color_2, in.uv.y);
+#ifdef DEPTH_CLAMP_ORTHO
+    out.frag_depth = in.clip_position_unclamped.z;
+#endif
+
+    return out;
+}
+#else
+#ifdef GRASS_ALPHA_MASK
+// depth only passes still have to discard the masked parts of the blade
+@fragment
+fn fragment(in: VertexOutput) {
+    blade_albedo(in);
+}
+#endif
+#endif
+
+#ifdef DEFERRED_PREPASS
+// same packing as `pbr_deferred_functions::deferred_gbuffer_from_pbr_input`, which can't be imported here
+// because it requires the prepass_io vertex output
+fn blade_gbuffer(in: pbr_types::PbrInput) -> vec4<u32> {
+    let occlusion = dot(in.occlusion, vec3<f32>(0.2126, 0.7152, 0.0722));
+#ifdef WEBGL2
+    let props = pbr_deferred_types::pack_unorm3x4_plus_unorm_20_(vec4<f32>(in.material.reflectance, in.material.metallic, occlusion, in.frag_coord.z));
+#else
+    let props = pbr_deferred_types::pack_unorm4x8_(vec4<f32>(in.material.reflectance, in.material.metallic, occlusion, 0.0));
+#endif
+    let flags = pbr_deferred_types::deferred_flags_from_mesh_material_flags(in.flags, in.material.flags);
+    let base_color_srgb = pow(in.material.base_color.rgb, vec3<f32>(1.0 / 2.2));
+
+    return vec4<u32>(
+        pbr_deferred_types::pack_unorm4x8_(vec4<f32>(base_color_srgb, in.material.perceptual_roughness)),
+        rgb9e5::vec3_to_rgb9e5_(in.material.emissive.rgb),
+        props,
+        pbr_deferred_types::pack_24bit_normal_and_flags(octahedral_encode(normalize(in.N)), flags),
+    );
+}
+#endif
+#else
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> @location(0) vec4<f32> {
+    let albedo = blade_albedo(in);
+    let normal = shading_normal(in, is_front);
+
+#ifdef GRASS_PBR
+    let pbr_input = blade_pbr_input(in, normal, albedo);
+    let output_color = pbr_functions::apply_pbr_lighting(pbr_input);
+
+    var final_color = pbr_functions::main_pass_post_lighting_processing(pbr_input, output_color);
+#else
+    let color = colors[in.blade_type];
+    let blade = blades[in.blade_type];
+    let base_color_gradient = cover_color(in, blade_color(in) * vec4<f32>(albedo.rgb * in.tint, 1.0));
     let ao = mix(color.ao, vec4<f32>(1.0, 1.0, 1.0, 1.0), in.uv.y);
 
     let distance = length(view.world_position - in.world_position);
@@ -200,11 +452,202 @@ fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> @locatio
         color_gradient += (base_color_gradient * lights.directional_lights[i].color * shadow) * 0.1;
     }
 
-    let final_color = ((color_gradient + specular) * ndotl * world_ndotl * ao);
+    var punctual_color = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+
+    let is_orthographic = view.projection[3].w == 1.0;
+    let cluster_index = clustered_forward::fragment_cluster_index(in.clip_position.xy, view_z, is_orthographic);
+    let offset_and_counts = clustered_forward::unpack_offset_and_counts(cluster_index);
+
+    for (var i: u32 = offset_and_counts[0]; i < offset_and_counts[0] + offset_and_counts[1]; i = i + 1u) {
+        let light_id = clustered_forward::get_light_id(i);
+
+        var shadow = 1.0;
+        if ((point_lights.data[light_id].flags & mesh_view_types::POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = clamp(shadows::fetch_point_shadow(light_id, vec4<f32>(in.world_position, 1.0), in.world_normal), 0.1, 1.0);
+        }
+
+        punctual_color += punctual_light(light_id, in.world_position, normal, in.normal, in.world_normal, view_dir, base_color_gradient, spec_strength, shadow);
+    }
+
+    for (var i: u32 = offset_and_counts[0] + offset_and_counts[1]; i < offset_and_counts[0] + offset_and_counts[1] + offset_and_counts[2]; i = i + 1u) {
+        let light_id = clustered_forward::get_light_id(i);
+
+        var shadow = 1.0;
+        if ((point_lights.data[light_id].flags & mesh_view_types::POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = clamp(shadows::fetch_spot_shadow(light_id, vec4<f32>(in.world_position, 1.0), in.world_normal), 0.1, 1.0);
+        }
+
+        let light = &point_lights.data[light_id];
+        var spot_dir = vec3<f32>((*light).light_custom_data.x, 0.0, (*light).light_custom_data.y);
+        spot_dir.y = sqrt(max(0.0, 1.0 - spot_dir.x * spot_dir.x - spot_dir.z * spot_dir.z));
+        if (((*light).flags & mesh_view_types::POINT_LIGHT_FLAGS_SPOT_LIGHT_Y_NEGATIVE) != 0u) {
+            spot_dir.y = -spot_dir.y;
+        }
+        let cd = dot(-spot_dir, normalize((*light).position_radius.xyz - in.world_position));
+        let attenuation = saturate(cd * (*light).light_custom_data.z + (*light).light_custom_data.w);
+
+        punctual_color += punctual_light(light_id, in.world_position, normal, in.normal, in.world_normal, view_dir, base_color_gradient, spec_strength, shadow) * attenuation * attenuation;
+    }
+
+    var final_color = ((color_gradient + specular) * ndotl * world_ndotl * ao) + punctual_color * ao;
 
+    if (fog.mode != mesh_view_types::FOG_MODE_OFF) {
+        final_color = pbr_functions::apply_fog(fog, final_color, in.world_position, view.world_position);
+    }
+#endif
+
+#ifdef GRASS_ALPHA_TO_COVERAGE
+    final_color.a = albedo.a;
+#endif
     return final_color;
 }
 
+// point and spot lights use the same blade lighting as the directional lights, attenuated by distance
+fn punctual_light(
+    light_id: u32,
+    world_position: vec3<f32>,
+    normal: vec3<f32>,
+    blade_normal: vec3<f32>,
+    world_normal: vec3<f32>,
+    view_dir: vec3<f32>,
+    base_color: vec4<f32>,
+    spec_strength: f32,
+    shadow: f32,
+) -> vec4<f32> {
+    let light = &point_lights.data[light_id];
+    let light_to_frag = (*light).position_radius.xyz - world_position;
+    let distance_square = dot(light_to_frag, light_to_frag);
+    let range_attenuation = lighting::getDistanceAttenuation(distance_square, (*light).color_inverse_square_range.w);
+    let light_color = vec4<f32>((*light).color_inverse_square_range.rgb * range_attenuation, 1.0);
+
+    let light_dir = normalize(light_to_frag);
+    let reflect_dir = reflect(light_dir, blade_normal);
+    let spec = pow(max(dot(view_dir, reflect_dir), 0.0), 32.);
+
+    let world_ndotl = clamp(dot(world_normal, light_dir), 0., 1.);
+    let ndotl = clamp(dot(normal, light_dir), world_ndotl, 1.0);
+
+    var specular = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+    if (shadow == 1.0) {
+        specular = spec_strength * spec * light_color;
+    }
+
+    let color_gradient = (base_color * light_color * shadow) * 0.1;
+
+    return (color_gradient + specular) * ndotl * world_ndotl;
+}
+#endif
+
+// the blade normal bent across its width by the curve, facing the camera on the back side
+fn shading_normal(in: VertexOutput, is_front: bool) -> vec3<f32> {
+    let blade = blades[in.blade_type];
+    var normal = in.normal;
+
+    let uv_x_transformed = in.uv.x * 2.0 - 1.0;
+    let curve = blade.curve + blade.curve_variance * fract(in.seed * 313.);
+    var normal_curve = curve * -1.;
+
+    if (!is_front) {
+        normal = -normal;
+        normal_curve = curve;
+    }
+    var shading_normal = normalize(rotate_vector(normal, in.bezier_tangent, normal_curve * uv_x_transformed));
+
+#ifdef GRASS_NORMAL_TEXTURE
+    // tangent space with y pointing along the blade towards the tip
+    let bitangent = normalize(in.bezier_tangent);
+    let tangent = normalize(cross(bitangent, shading_normal));
+    let tangent_normal = textureSample(normal_texture, normal_sampler, in.texture_uv).rgb * 2.0 - 1.0;
+    shading_normal = normalize(tangent * tangent_normal.x + bitangent * tangent_normal.y + shading_normal * tangent_normal.z);
+#endif
+
+    return shading_normal;
+}
+
+// texture color of the blade, discarding the masked parts
+fn blade_albedo(in: VertexOutput) -> vec4<f32> {
+    var albedo = vec4<f32>(1.0);
+
+#ifdef GRASS_ALBEDO_TEXTURE
+    albedo = textureSample(albedo_texture, albedo_sampler, in.texture_uv);
+#endif
+#ifdef GRASS_ALPHA_TEXTURE
+    albedo.a = textureSample(alpha_texture, alpha_sampler, in.texture_uv).r;
+#endif
+
+#ifdef GRASS_ALPHA_MASK
+#ifndef GRASS_ALPHA_TO_COVERAGE
+    if (albedo.a < material.alpha_cutoff) {
+        discard;
+    }
+#endif
+#endif
+
+    return albedo;
+}
+
+#ifdef GRASS_PBR
+fn blade_pbr_input(in: VertexOutput, normal: vec3<f32>, albedo: vec4<f32>) -> pbr_types::PbrInput {
+    var pbr_input = pbr_types::pbr_input_new();
+    let color = colors[in.blade_type];
+    let blade = blades[in.blade_type];
+
+    pbr_input.material.base_color = cover_color(in, blade_color(in) * albedo * vec4<f32>(in.tint, 1.0));
+    pbr_input.material.perceptual_roughness = blade.roughness;
+    pbr_input.material.reflectance = blade.reflectance;
+    pbr_input.material.metallic = 0.0;
+    // light passing through the thin blades is what gives grass its glow when backlit
+    pbr_input.material.diffuse_transmission = blade.translucency;
+    pbr_input.material.thickness = blade.thickness;
+    pbr_input.material.flags |= pbr_types::STANDARD_MATERIAL_FLAGS_FOG_ENABLED_BIT;
+
+    pbr_input.occlusion = mix(color.ao, vec4<f32>(1.0, 1.0, 1.0, 1.0), in.uv.y).rgb;
+    pbr_input.frag_coord = in.clip_position;
+    pbr_input.world_position = vec4<f32>(in.world_position, 1.0);
+    pbr_input.world_normal = in.world_normal;
+    pbr_input.N = normal;
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.V = pbr_functions::calculate_view(pbr_input.world_position, pbr_input.is_orthographic);
+    pbr_input.flags = mesh_types::MESH_FLAGS_SHADOW_RECEIVER_BIT | mesh_types::MESH_FLAGS_TRANSMITTED_SHADOW_RECEIVER_BIT;
+
+    return pbr_input;
+}
+#endif
+
+// root to tip gradient of the blade, through the season ramp and the per-blade variation
+fn blade_color(in: VertexOutput) -> vec4<f32> {
+    let color = colors[in.blade_type];
+    let gradient = mix(color.color_1, color.color_2, in.uv.y);
+
+    // each blade is a little ahead of or behind the season
+    let u = clamp(season.value + mix(-season.variance, season.variance, fract(in.seed * 6007.)), 0.0, 1.0);
+    let ramp = textureSampleLevel(season_ramp, season_sampler, vec2<f32>(u, 1.0 - in.uv.y), 0.0);
+    let seasonal = mix(gradient.rgb, ramp.rgb, ramp.a * season.strength);
+
+    return vec4<f32>(vary_color(seasonal, blades[in.blade_type], in.seed), gradient.a);
+}
+
+// how much snow, dust or frost settles on the blade, from the amount and the world space mask
+fn blade_cover(position: vec3<f32>) -> f32 {
+    let uv = (position.xz - coverage.mask_min) / (coverage.mask_max - coverage.mask_min);
+    let mask = textureSampleLevel(coverage_mask, coverage_sampler, uv, 0.0).r;
+    return clamp(coverage.amount * mix(1.0, mask, coverage.masked), 0.0, 1.0);
+}
+
+// the cover settles from the tip down, further down the more covered the blade is
+fn cover_color(in: VertexOutput, color: vec4<f32>) -> vec4<f32> {
+    let line = 1.0 - in.cover * coverage.reach;
+    let covered = smoothstep(line - 0.05, line + 0.05, in.uv.y) * step(0.001, in.cover);
+    return vec4<f32>(mix(color.rgb, coverage.color.rgb, covered), color.a);
+}
+
+// shifts the hue of a blade around the grey axis and scales its brightness, both at random
+fn vary_color(color: vec3<f32>, blade: Blade, seed: f32) -> vec3<f32> {
+    let hue = mix(-blade.hue_variance, blade.hue_variance, fract(seed * 4729.));
+    let brightness = 1.0 + mix(-blade.brightness_variance, blade.brightness_variance, fract(seed * 1571.));
+    return max(rotate_vector(color, vec3<f32>(0.57735027), hue), vec3<f32>(0.0)) * brightness;
+}
+
 fn rotate_vector(v: vec3<f32>, n: vec3<f32>, degrees: f32) -> vec3<f32> {
     let theta = degrees * PI / 180.;
     let cos_theta = cos(theta);
@@ -257,33 +700,3 @@ fn rotate_align(v1: vec3<f32>, v2: vec3<f32>) -> mat3x3<f32> {
 
     return result;
 }
-
-fn sample_wind_map(uv: vec2<f32>, speed: f32) -> vec4<f32> {
-    let texture_size = textureDimensions(t_wind_map);
-    
-    let rad = wind.direction * PI / 180.0;
-    let direction = vec2<f32>(cos(rad), sin(rad));
-    
-    let scrolled_uv = uv + direction * globals.time * speed;
-    
-    let pixel_coords = vec2<i32>(fract(scrolled_uv) * vec2<f32>(texture_size));
-    return textureLoad(t_wind_map, pixel_coords, 0);
-}
-
-const identity_matrix: mat4x4<f32> = mat4x4<f32>(
-    vec4<f32>(1.0, 0.0, 0.0, 0.0),
-    vec4<f32>(0.0, 1.0, 0.0, 0.0),
-    vec4<f32>(0.0, 0.0, 1.0, 0.0),
-    vec4<f32>(0.0, 0.0, 0.0, 1.0)
-);
-
-fn unpack_float(rgb: vec3<f32>) -> f32 {
-    let r = rgb.r * 255.0;
-    let g = rgb.g * 255.0;
-    let b = rgb.b * 255.0;
-
-    let noise_scaled = r * 65536.0 + g * 256.0 + b;
-    let noise = noise_scaled / 16777215.0;
-
-    return noise;
-}
\ No newline at end of file
@@ -0,0 +1,133 @@
+#import bevy_procedural_grass::wind::{wind, wind_field}
+
+struct CullView {
+    planes: array<vec4<f32>, 6>,
+    camera_position: vec3<f32>,
+    cull_distance: f32,
+    lod_distance: f32,
+};
+@group(0) @binding(0)
+var<uniform> view: CullView;
+
+// GrassData is quantized to 5 words, so it is read as raw words
+@group(1) @binding(0)
+var<storage, read> instances: array<u32>;
+// high LOD instances first, then low LOD instances offset by the total instance count
+@group(1) @binding(1)
+var<storage, read_write> culled_instances: array<u32>;
+
+struct DrawIndirect {
+    count: u32,
+    instance_count: atomic<u32>,
+    first: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+// high LOD draws of every variant, then the low LOD draws
+@group(1) @binding(2)
+var<storage, read_write> indirect: array<DrawIndirect>;
+// first instance of each variant, the instances are grouped by variant
+@group(1) @binding(3)
+var<storage, read> variant_starts: array<u32>;
+
+struct Blade {
+    length: f32,
+    width: f32,
+    tilt: f32,
+    tilt_variance: f32,
+    p1_flexibility: f32,
+    p2_flexibility: f32,
+    curve: f32,
+    specular: f32,
+    roughness: f32,
+    reflectance: f32,
+    translucency: f32,
+    thickness: f32,
+    length_variance: f32,
+    width_variance: f32,
+    flexibility_variance: f32,
+    curve_variance: f32,
+    hue_variance: f32,
+    brightness_variance: f32,
+    facing: u32,
+    facing_direction: f32,
+}
+@group(2) @binding(1)
+var<uniform> blades: array<Blade, #{MAX_GRASS_VARIANTS}u>;
+
+struct Chunks {
+    size: f32,
+    camera_position: vec3<f32>,
+};
+@group(2) @binding(2)
+var<uniform> chunks: Chunks;
+
+const INSTANCE_STRIDE: u32 = 5u;
+
+// sign extends the low and high 16 bits of a word
+fn unpack_i16(word: u32) -> vec2<i32> {
+    return vec2<i32>(bitcast<i32>(word << 16u) >> 16u, bitcast<i32>(word) >> 16u);
+}
+
+@compute @workgroup_size(64, 1, 1)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let count = arrayLength(&instances) / INSTANCE_STRIDE;
+    if (id.x >= count) {
+        return;
+    }
+
+    let base = id.x * INSTANCE_STRIDE;
+    let chunk_uvw = vec3<f32>(
+        f32(instances[base] & 0xffffu),
+        f32(instances[base] >> 16u),
+        f32(instances[base + 1u] & 0xffffu),
+    ) / 65535.0;
+    let chunk = vec3<f32>(vec2<f32>(unpack_i16(instances[base + 2u])), f32(unpack_i16(instances[base + 3u]).x));
+    let position = (chunk + chunk_uvw) * chunks.size;
+    let blade_type = instances[base + 4u] >> 24u;
+    let blade = blades[blade_type];
+
+    // the strongest sway of the wind and the wind zones, matches WindReach::strongest_wind
+    var amplitude = abs(wind.amplitude);
+    var oscillation = abs(wind.oscillation);
+    for (var i = 0u; i < min(wind_field.zone_count, #{MAX_WIND_ZONES}u); i = i + 1u) {
+        amplitude = max(amplitude, abs(wind_field.zones[i].wind.amplitude));
+        oscillation = max(oscillation, abs(wind_field.zones[i].wind.oscillation));
+    }
+
+    // the gusts and impulses push on top of the wind, matches WindReach::push
+    var push = abs(wind_field.gusts.strength);
+    for (var i = 0u; i < min(wind_field.impulse_count, #{MAX_WIND_IMPULSES}u); i = i + 1u) {
+        push += abs(wind_field.impulses[i].strength);
+    }
+
+    // furthest a blade tip can reach from its root, matches Blade::max_reach
+    let radius = blade.length * (1.0 + max(blade.length_variance, 0.0))
+        + 0.5 * amplitude
+        + oscillation
+        + push
+        + blade.width * (1.0 + max(blade.width_variance, 0.0));
+
+    let view_distance = distance(position, view.camera_position);
+    if (view_distance > view.cull_distance + radius) {
+        return;
+    }
+
+    for (var i = 0u; i < 6u; i = i + 1u) {
+        if (dot(view.planes[i], vec4<f32>(position, 1.0)) + radius <= 0.0) {
+            return;
+        }
+    }
+
+    var lod = 0u;
+    if (view_distance > view.lod_distance) {
+        lod = 1u;
+    }
+
+    let variants = arrayLength(&variant_starts);
+    let index = atomicAdd(&indirect[lod * variants + blade_type].instance_count, 1u);
+    let out_base = (lod * count + variant_starts[blade_type] + index) * INSTANCE_STRIDE;
+    for (var i = 0u; i < INSTANCE_STRIDE; i = i + 1u) {
+        culled_instances[out_base + i] = instances[base + i];
+    }
+}
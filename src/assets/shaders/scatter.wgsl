@@ -0,0 +1,136 @@
+#import bevy_pbr::mesh_view_bindings::globals
+#import bevy_pbr::view_transformations::position_world_to_clip
+#import bevy_pbr::forward_io::VertexOutput
+#import bevy_pbr::utils::PI
+#import bevy_pbr::utils::octahedral_decode
+#import bevy_procedural_grass::wind::{wind, zoned_wind_sway, gust_sway, impulse_push}
+
+// the mesh attributes of `forward_io::Vertex`, with the instance after them
+struct Vertex {
+    @location(0) position: vec3<f32>,
+#ifdef VERTEX_NORMALS
+    @location(1) normal: vec3<f32>,
+#endif
+#ifdef VERTEX_UVS
+    @location(2) uv: vec2<f32>,
+#endif
+#ifdef VERTEX_TANGENTS
+    @location(4) tangent: vec4<f32>,
+#endif
+#ifdef VERTEX_COLORS
+    @location(5) color: vec4<f32>,
+#endif
+
+    // quantized GrassData, see decode_instance
+    @location(8) i_position: vec4<u32>,
+    @location(9) i_chunk: vec4<i32>,
+    @location(10) i_packed: u32,
+};
+
+struct Instance {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    seed: f32,
+    scale: f32,
+};
+
+struct Scatter {
+    chunk_size: f32,
+    min_scale: f32,
+    max_scale: f32,
+    sway: f32,
+    random_rotation: u32,
+    align_to_normal: u32,
+    _padding: vec2<u32>,
+};
+@group(2) @binding(1)
+var<uniform> scatter: Scatter;
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let instance = decode_instance(vertex);
+
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if scatter.align_to_normal != 0u {
+        up = instance.normal;
+    }
+    var angle = 0.0;
+    if scatter.random_rotation != 0u {
+        angle = 2.0 * PI * instance.seed;
+    }
+    let yaw = mat3x3<f32>(
+        vec3<f32>(cos(angle), 0.0, -sin(angle)),
+        vec3<f32>(0.0, 1.0, 0.0),
+        vec3<f32>(sin(angle), 0.0, cos(angle)),
+    );
+    let rotation = rotate_align(vec3<f32>(0.0, 1.0, 0.0), up) * yaw;
+
+    var position = rotation * (vertex.position * instance.scale);
+
+    // bends with the wind like the blades, more the higher above the root
+    if scatter.sway != 0.0 {
+        let height = max(vertex.position.y, 0.0) * instance.scale;
+        // without a base normal the sway has no oscillation, like a stiff blade
+        let push = gust_sway(instance.position, 0.0) + impulse_push(instance.position);
+        let sway = (zoned_wind_sway(wind, instance.position, 0.0, vec2<f32>(0.0), globals.time) + push) * scatter.sway * height;
+        position += vec3<f32>(sway.x, 0.0, sway.y);
+    }
+
+    // the instances are placed in world space, the mesh uniform at index 0 is the identity
+    out.world_position = vec4<f32>(instance.position + position, 1.0);
+    out.position = position_world_to_clip(out.world_position.xyz);
+
+#ifdef VERTEX_NORMALS
+    out.world_normal = normalize(rotation * vertex.normal);
+#else
+    out.world_normal = up;
+#endif
+#ifdef VERTEX_UVS
+    out.uv = vertex.uv;
+#endif
+#ifdef VERTEX_TANGENTS
+    out.world_tangent = vec4<f32>(normalize(rotation * vertex.tangent.xyz), vertex.tangent.w);
+#endif
+#ifdef VERTEX_COLORS
+    out.color = vertex.color;
+#endif
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = 0u;
+#endif
+
+    return out;
+}
+
+fn decode_instance(vertex: Vertex) -> Instance {
+    var out: Instance;
+
+    let chunk_uvw = vec3<f32>(vertex.i_position.xyz) / 65535.0;
+    out.position = (vec3<f32>(vertex.i_chunk.xyz) + chunk_uvw) * scatter.chunk_size;
+    let normal = vec2<u32>(vertex.i_position.w & 0xffu, vertex.i_position.w >> 8u);
+    out.normal = octahedral_decode(vec2<f32>(normal) / 255.0);
+    out.seed = f32(vertex.i_packed & 0xffffu) / 65535.0;
+    out.scale = mix(scatter.min_scale, scatter.max_scale, f32((vertex.i_packed >> 16u) & 0xffu) / 255.0);
+
+    return out;
+}
+
+fn rotate_align(v1: vec3<f32>, v2: vec3<f32>) -> mat3x3<f32> {
+    let axis = cross(v1, v2);
+
+    let cos_a = dot(v1, v2);
+    // straight down has no single rotation, flip around x
+    if cos_a < -0.9999 {
+        return mat3x3<f32>(vec3<f32>(1.0, 0.0, 0.0), vec3<f32>(0.0, -1.0, 0.0), vec3<f32>(0.0, 0.0, -1.0));
+    }
+    let k = 1.0 / (1.0 + cos_a);
+
+    let result = mat3x3f(
+            (axis.x * axis.x * k) + cos_a, (axis.x * axis.y * k) + axis.z, (axis.x * axis.z * k) - axis.y,
+            (axis.y * axis.x * k) - axis.z, (axis.y * axis.y * k) + cos_a,  (axis.y * axis.z * k) + axis.x,
+            (axis.z * axis.x * k) + axis.y, (axis.z * axis.y * k) - axis.x, (axis.z * axis.z * k) + cos_a
+        );
+
+    return result;
+}
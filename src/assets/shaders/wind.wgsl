@@ -0,0 +1,152 @@
+#define_import_path bevy_procedural_grass::wind
+
+#import bevy_pbr::utils::PI
+
+// the wind bindings of the grass, scatter and grass cull shaders, laid out like the wind uniforms in prepare.rs
+// the sway functions are mirrored by GrassWind::sample in wind.rs, keep the two in sync
+
+struct Wind {
+    speed: f32,
+    amplitude: f32,
+    frequency: f32,
+    direction: f32,
+    oscillation: f32,
+    scale: f32,
+    _padding: vec2<f32>,
+};
+@group(3) @binding(0)
+var<uniform> wind: Wind;
+
+@group(3) @binding(1)
+var t_wind_map: texture_2d<f32>;
+
+@group(3) @binding(2)
+var<uniform> previous_wind: Wind;
+
+struct WindZone {
+    world_to_local: mat4x4<f32>,
+    wind: Wind,
+    // the sphere radius in x
+    half_extents: vec3<f32>,
+    shape: u32,
+    falloff: f32,
+    blend: f32,
+    _padding: vec2<f32>,
+};
+const WIND_ZONE_BOX: u32 = 1u;
+struct Gusts {
+    travel_direction: vec2<f32>,
+    offset: f32,
+    speed: f32,
+    spacing: f32,
+    width: f32,
+    strength: f32,
+    _padding: f32,
+};
+struct WindImpulse {
+    position: vec3<f32>,
+    radius: f32,
+    strength: f32,
+    falloff: f32,
+    front: f32,
+    ring: u32,
+};
+struct WindField {
+    gusts: Gusts,
+    zones: array<WindZone, #{MAX_WIND_ZONES}u>,
+    zone_count: u32,
+    impulse_count: u32,
+    impulses: array<WindImpulse, #{MAX_WIND_IMPULSES}u>,
+};
+@group(3) @binding(3)
+var<uniform> wind_field: WindField;
+
+// how far the wind pushes a blade tip, the oscillation sways it along base_normal
+fn wind_sway(wind: Wind, position: vec3<f32>, seed: f32, base_normal: vec2<f32>, time: f32) -> vec2<f32> {
+    let rad = wind.direction * PI / 180.0;
+    let wind_direction = vec2<f32>(cos(rad), sin(rad));
+
+    let random_point = vec2<f32>(fract(position.x * 0.1 * seed), fract(position.y * 0.1 * seed));
+    let r = sample_wind_map(random_point, wind, time).r;
+
+    let wind_pos = fract(position.xz / wind.scale);
+    let t = unpack_float(sample_wind_map(wind_pos, wind, time).rgb);
+
+    return -wind_direction * (0.5 * sin(t * wind.frequency)) * wind.amplitude + base_normal * sin(r * 0.2) * wind.oscillation;
+}
+
+// the sways of the zones are blended rather than their winds, blending the speed would shear the scrolling wind map
+fn zoned_wind_sway(wind: Wind, position: vec3<f32>, seed: f32, base_normal: vec2<f32>, time: f32) -> vec2<f32> {
+    var sway = wind_sway(wind, position, seed, base_normal, time);
+    for (var i = 0u; i < min(wind_field.zone_count, #{MAX_WIND_ZONES}u); i = i + 1u) {
+        let zone = wind_field.zones[i];
+        let weight = wind_zone_weight(zone, position);
+        if (weight > 0.0) {
+            sway = mix(sway, wind_sway(zone.wind, position, seed, base_normal, time), weight);
+        }
+    }
+    return sway;
+}
+
+fn wind_zone_weight(zone: WindZone, position: vec3<f32>) -> f32 {
+    let p = (zone.world_to_local * vec4<f32>(position, 1.0)).xyz;
+    // distance outside the shape
+    var d = length(p) - zone.half_extents.x;
+    if (zone.shape == WIND_ZONE_BOX) {
+        d = length(max(abs(p) - zone.half_extents, vec3<f32>(0.0)));
+    }
+    return zone.blend * (1.0 - smoothstep(0.0, max(zone.falloff, 0.0001), d));
+}
+
+// how far the gust fronts push, `since_upload` seconds after the gusts were uploaded at the time of the frame
+fn gust_sway(position: vec3<f32>, since_upload: f32) -> vec2<f32> {
+    let gusts = wind_field.gusts;
+    let offset = gusts.offset + gusts.speed * since_upload;
+    let distance = dot(position.xz, gusts.travel_direction) - offset;
+    let to_front = abs(fract(distance / gusts.spacing + 0.5) - 0.5) * gusts.spacing;
+    return gusts.travel_direction * gusts.strength * (1.0 - smoothstep(0.0, max(gusts.width * 0.5, 0.0001), to_front));
+}
+
+// the impulses push outward from their centers, only horizontally
+fn impulse_push(position: vec3<f32>) -> vec2<f32> {
+    var push = vec2<f32>(0.0);
+    for (var i = 0u; i < min(wind_field.impulse_count, #{MAX_WIND_IMPULSES}u); i = i + 1u) {
+        let impulse = wind_field.impulses[i];
+        let offset = position.xz - impulse.position.xz;
+        let distance = length(offset);
+        if (distance >= impulse.radius || distance < 0.0001) {
+            continue;
+        }
+
+        let falloff = max(impulse.falloff, 0.0001);
+        var weight = 1.0 - smoothstep(impulse.radius - falloff, impulse.radius, distance);
+        if (impulse.ring != 0u) {
+            weight *= 1.0 - smoothstep(0.0, falloff, abs(distance - impulse.front));
+        }
+        push += offset / distance * impulse.strength * weight;
+    }
+    return push;
+}
+
+fn sample_wind_map(uv: vec2<f32>, wind: Wind, time: f32) -> vec4<f32> {
+    let texture_size = textureDimensions(t_wind_map);
+
+    let rad = wind.direction * PI / 180.0;
+    let direction = vec2<f32>(cos(rad), sin(rad));
+
+    let scrolled_uv = uv + direction * time * wind.speed;
+
+    let pixel_coords = vec2<i32>(fract(scrolled_uv) * vec2<f32>(texture_size));
+    return textureLoad(t_wind_map, pixel_coords, 0);
+}
+
+fn unpack_float(rgb: vec3<f32>) -> f32 {
+    let r = rgb.r * 255.0;
+    let g = rgb.g * 255.0;
+    let b = rgb.b * 255.0;
+
+    let noise_scaled = r * 65536.0 + g * 256.0 + b;
+    let noise = noise_scaled / 16777215.0;
+
+    return noise;
+}
@@ -1,9 +1,11 @@
+use std::ops::Range;
+
 use bevy::{prelude::*, utils::HashMap, render::{primitives::{Frustum, Aabb}, extract_component::ExtractComponent}, ecs::query::QueryItem, math::{Vec3A, Affine3A}};
 
-use crate::render::instance::GrassChunkData;
-use super::config::GrassConfig;
+use crate::render::instance::{GrassChunkData, GrassData};
+use super::{config::GrassConfig, grass::Grass, wind::{GrassWind, WindReach}};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub enum GrassLOD {
     High,
     Low,
@@ -15,23 +17,47 @@ pub enum CullDimension {
     D3,
 }
 
+/// Where the blade instances are kept once they are generated, like `RenderAssetUsages` for meshes and images.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum GrassDataUsage {
+    /// Keep the instances in [`GrassChunks::chunks`] as well as on the GPU.
+    #[default]
+    MainAndRenderWorld,
+    /// Only keep the instances on the GPU, [`GrassChunks::chunks`] is cleared once the instance buffer is built and the
+    /// [`GrassChunkData`] instances are moved to the render world when it is extracted.
+    /// The grass has to be regenerated to change it.
+    RenderWorld,
+}
+
 impl Default for CullDimension {
     fn default() -> Self {
         Self::D2
     }
 }
 
+/// A range of instances in [`GrassChunks::instances`] drawn with the same LOD mesh, all of the same variant.
 pub type GrassRenderInfo = (
     GrassLOD, 
-    Handle<GrassChunkData>, 
+    usize,
+    Range<u32>, 
 );
 
 #[derive(Component, Clone)]
 pub struct GrassChunks {
     pub chunk_size: f32,
     pub cull_dimension: CullDimension,
-    pub chunks: HashMap<(i32, i32, i32), GrassChunkData>,
-    pub loaded: HashMap<(i32, i32, i32), Handle<GrassChunkData>>,
+    pub usage: GrassDataUsage,
+    /// Blade instances of each chunk, empty with [`GrassDataUsage::RenderWorld`].
+    pub chunks: HashMap<(i32, i32, i32), Vec<GrassData>>,
+    /// World space bounds of the blade roots in each chunk, expanded by the blade reach when culling.
+    pub bounds: HashMap<(i32, i32, i32), Aabb>,
+    /// Every blade of the grass in a single instance buffer, grouped by variant then chunk.
+    pub instances: Handle<GrassChunkData>,
+    /// Range of each variant of a chunk in [`GrassChunks::instances`].
+    pub ranges: HashMap<(i32, i32, i32), Vec<Range<u32>>>,
+    /// Chunks that passed culling, sorted by coordinates. The draw ranges are only rebuilt when this changes.
+    pub visible: Vec<((i32, i32, i32), GrassLOD)>,
+    /// Visible chunks merged into as few instance ranges as possible.
     pub render: Vec<GrassRenderInfo>,
 }
 
@@ -40,92 +66,161 @@ impl Default for GrassChunks {
         Self {
             chunk_size: 30.,
             cull_dimension: CullDimension::D2,
+            usage: GrassDataUsage::default(),
             chunks: HashMap::new(),
-            loaded: HashMap::new(),
+            bounds: HashMap::new(),
+            instances: Handle::default(),
+            ranges: HashMap::new(),
+            visible: Vec::new(),
             render: Vec::new(),
         }
     }
 }
 
+impl GrassChunks {
+    /// Uploads the generated [`GrassChunks::chunks`] into a single instance buffer and computes their bounds.
+    pub(crate) fn upload(&mut self, variants: usize, assets: &mut Assets<GrassChunkData>) {
+        let chunk_size = self.chunk_size;
+        self.bounds = self.chunks.iter()
+            .filter_map(|(chunk_coords, data)| {
+                Aabb::enclosing(data.iter().map(|instance| instance.world_position(chunk_size))).map(|aabb| (*chunk_coords, aabb))
+            })
+            .collect();
+
+        // upload every chunk once into one instance buffer, grouped by variant so each variant is drawn with its own mesh,
+        // then sorted so neighbouring chunks are contiguous
+        let mut chunk_coords: Vec<_> = self.chunks.keys().copied().collect();
+        chunk_coords.sort();
+
+        let mut instances = Vec::new();
+        let mut variant_ranges = Vec::new();
+        let mut ranges: HashMap<_, Vec<Range<u32>>> = HashMap::new();
+        for variant in 0..variants {
+            let variant_start = instances.len() as u32;
+            for chunk_coords in chunk_coords.iter() {
+                let start = instances.len() as u32;
+                instances.extend(self.chunks[chunk_coords].iter().filter(|instance| instance.blade_type() as usize == variant));
+                ranges.entry(*chunk_coords).or_default().push(start..instances.len() as u32);
+            }
+            variant_ranges.push(variant_start..instances.len() as u32);
+        }
+
+        self.ranges = ranges;
+        self.instances = assets.add(GrassChunkData {
+            instances,
+            variants: variant_ranges,
+            usage: self.usage,
+        });
+        if self.usage == GrassDataUsage::RenderWorld {
+            self.chunks = HashMap::new();
+        }
+        self.visible.clear();
+        self.render.clear();
+    }
+}
+
 impl ExtractComponent for GrassChunks {
     type Query = &'static GrassChunks;
     type Filter = ();
     type Out = RenderGrassChunks;
 
     fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self::Out> {
-        Some(RenderGrassChunks(item.render.clone()))
+        Some(RenderGrassChunks {
+            instances: item.instances.id(),
+            ranges: item.render.clone(),
+            chunk_size: item.chunk_size,
+        })
     }
 }
 
-#[derive(Component, Default, Clone)]
-pub struct RenderGrassChunks(pub Vec<GrassRenderInfo>);
+#[derive(Component, Clone)]
+pub struct RenderGrassChunks {
+    pub instances: AssetId<GrassChunkData>,
+    pub ranges: Vec<GrassRenderInfo>,
+    /// Needed to decode the chunk relative [`GrassData`](crate::render::instance::GrassData) positions.
+    pub chunk_size: f32,
+}
 
 pub(crate) fn grass_culling(
-    mut query: Query<&mut GrassChunks>,
+    mut query: Query<(&Grass, &mut GrassChunks, Option<&GrassWind>)>,
     camera_query: Query<(&Transform, &Frustum)>,
-    mut grass_asset: ResMut<Assets<GrassChunkData>>,
     grass_config: Res<GrassConfig>,
+    wind_reach: WindReach,
 ) {
-    for mut chunks in query.iter_mut() {
-        chunks.render.clear();
-        
-        for (transform, frustum) in camera_query.iter() {
+    for (grass, mut chunks, local_wind) in query.iter_mut() {
+        let wind = wind_reach.strongest_wind(local_wind);
+        let reach = grass.active_variants().iter().map(|variant| variant.blade.max_reach(&wind)).fold(0.0, f32::max)
+            + wind_reach.push();
+        cull_chunks(&mut chunks, reach, &camera_query, &grass_config);
+    }
+}
+
+/// Finds the chunks any camera can see and merges them into [`GrassChunks::render`].
+/// The bounds of the chunks are expanded by `reach`, how far the instances can reach from their roots.
+pub(crate) fn cull_chunks(
+    chunks: &mut GrassChunks,
+    reach: f32,
+    camera_query: &Query<(&Transform, &Frustum)>,
+    grass_config: &GrassConfig,
+) {
+    let reach = Vec3A::splat(reach);
+
+    // a chunk seen by several cameras is only drawn once, at the highest LOD any of them needs
+    let mut visible: HashMap<(i32, i32, i32), GrassLOD> = HashMap::new();
+    for (transform, frustum) in camera_query.iter() {
+        for (chunk_coords, bounds) in chunks.bounds.iter() {
             let aabb = Aabb {
-                center: Vec3A::splat(chunks.chunk_size / 2.),
-                half_extents: Vec3A::splat(chunks.chunk_size / 2.) + Vec3A::new(2., 2., 2.),
+                center: bounds.center,
+                half_extents: bounds.half_extents + reach,
             };
             
-            let chunk_coords: Vec<(i32, i32, i32)> = chunks.chunks.keys().cloned().collect();
+            let d3_distance = (Vec3::from(aabb.center) - transform.translation).length();
+
+            let lod_type = match d3_distance <= grass_config.lod_distance {
+                true => GrassLOD::High,
+                false => GrassLOD::Low,
+            };
+
+            let cull_distance = match chunks.cull_dimension {
+                CullDimension::D2 => (aabb.center.xz() - transform.translation.xz()).length(),
+                CullDimension::D3 => d3_distance,
+            };
             
-            let mut chunks_inside = Vec::new();
-            let mut chunks_outside = Vec::new();
-
-            for chunk_coords in chunk_coords.iter().cloned() {
-                let (x, y, z) = chunk_coords;
-                let world_pos = Vec3::new(x as f32, y as f32, z as f32) * chunks.chunk_size;
-                
-                let d3_distance = (world_pos + Vec3::from(aabb.center) - transform.translation).length();
-
-                let lod_type = match d3_distance <= grass_config.lod_distance {
-                    true => GrassLOD::High,
-                    false => GrassLOD::Low,
-                };
-
-                let cull_distance = match chunks.cull_dimension {
-                    CullDimension::D2 => ((world_pos.xz() + aabb.center.xz()) - transform.translation.xz()).length(),
-                    CullDimension::D3 => d3_distance,
-                };
-                
-                if frustum.intersects_obb(&aabb, &Affine3A::from_translation(world_pos), false, false) && cull_distance <= grass_config.cull_distance {
-                    chunks_inside.push((chunk_coords, lod_type));
-                } else {
-                    chunks_outside.push(chunk_coords);
+            if frustum.intersects_obb(&aabb, &Affine3A::IDENTITY, false, false) && cull_distance <= grass_config.cull_distance {
+                let lod = visible.entry(*chunk_coords).or_insert(lod_type);
+                if lod_type == GrassLOD::High {
+                    *lod = GrassLOD::High;
                 }
             }
-        
-            for chunk_coords in chunks_outside {
-                chunks.loaded.remove(&chunk_coords);
-            }
-        
-            for chunk_coords in chunks_inside.iter() {
-                if !chunks.loaded.contains_key(&chunk_coords.0) {
-                    let instance = &chunks.chunks.get(&chunk_coords.0).unwrap().0;
-                    let handle = grass_asset.add(GrassChunkData(instance.clone()));
-                    chunks.loaded.insert(chunk_coords.0, handle);
+        }
+    }
+
+    let mut visible: Vec<_> = visible.into_iter().filter(|(chunk_coords, _)| chunks.ranges.contains_key(chunk_coords)).collect();
+    visible.sort_by_key(|(chunk_coords, _)| *chunk_coords);
+
+    if visible == chunks.visible {
+        return;
+    }
+
+    // neighbouring chunks are next to each other in the instance buffer of each variant, so they merge into one draw
+    let mut render: Vec<GrassRenderInfo> = Vec::new();
+    let variants = chunks.ranges.values().next().map_or(0, Vec::len);
+    for lod in [GrassLOD::High, GrassLOD::Low] {
+        for variant in 0..variants {
+            let start = render.len();
+            for (chunk_coords, _) in visible.iter().filter(|(_, chunk_lod)| *chunk_lod == lod) {
+                let range = chunks.ranges[chunk_coords][variant].clone();
+                if range.is_empty() {
+                    continue;
                 }
-            }
-            
-            let mut render_chunks = Vec::new();
-            for chunk_coords in chunks_inside {
-                if let Some(handle) = chunks.loaded.get(&chunk_coords.0) {
-                    render_chunks.push((
-                        chunk_coords.1, 
-                        handle.clone(), 
-                    ));
+                match render[start..].last_mut() {
+                    Some((_, _, last)) if last.end == range.start => last.end = range.end,
+                    _ => render.push((lod, variant, range)),
                 }
             }
-
-            chunks.render.extend(render_chunks);
         }
     }
-}
\ No newline at end of file
+
+    chunks.visible = visible;
+    chunks.render = render;
+}
@@ -1,4 +1,4 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
 #[cfg(feature = "bevy-inspector-egui")]
 use bevy_inspector_egui::{InspectorOptions, inspector_options::ReflectInspectorOptions};
 
@@ -9,6 +9,10 @@ pub struct GrassConfig {
     pub cull_distance: f32,
     pub lod_distance: f32,
     pub displacement_resolution: u32,
+    /// Cull individual blades on the GPU with a compute pass and draw the survivors indirectly.
+    /// The blades are only culled against the view frustum and the cull distance, there is no Hi-Z occlusion culling.
+    /// Requires compute shader support, so it is disabled by default. It can be toggled at any time.
+    pub gpu_culling: bool,
 }
 
 impl Default for GrassConfig {
@@ -17,6 +21,15 @@ impl Default for GrassConfig {
             cull_distance: 200.,
             lod_distance: 50.,
             displacement_resolution: 90,
+            gpu_culling: false,
         }
     }
+}
+
+impl ExtractResource for GrassConfig {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        *source
+    }
 }
\ No newline at end of file
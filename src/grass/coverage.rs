@@ -0,0 +1,44 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+#[cfg(feature = "bevy-inspector-egui")]
+use bevy_inspector_egui::{InspectorOptions, inspector_options::ReflectInspectorOptions};
+
+/// Snow, dust or frost settling on the grass, whitening the blade tips and pressing the blades down.
+/// A weather system can animate it freely, changing it only rewrites a uniform.
+#[derive(Resource, Clone)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
+#[cfg_attr(feature = "bevy-inspector-egui", reflect(Resource, InspectorOptions))]
+pub struct GrassCoverage {
+    /// How covered the grass is, from 0 for bare grass to 1 for as much as the mask allows.
+    pub amount: f32,
+    pub color: Color,
+    /// How far down from the tip a fully covered blade is colored, from 0 to 1 of the blade.
+    pub reach: f32,
+    /// How much a fully covered blade is pressed down, from 0 for upright to 1 for flat.
+    pub flatten: f32,
+    /// Where the cover can settle, in the red channel, over [`GrassCoverage::mask_bounds`].
+    /// Without a mask the whole field is covered.
+    pub mask: Option<Handle<Image>>,
+    /// The world space XZ area the mask is stretched over.
+    pub mask_bounds: Rect,
+}
+
+impl Default for GrassCoverage {
+    fn default() -> Self {
+        Self {
+            amount: 0.0,
+            color: Color::rgb(0.9, 0.92, 0.95),
+            reach: 0.4,
+            flatten: 0.3,
+            mask: None,
+            mask_bounds: Rect::from_center_size(Vec2::ZERO, Vec2::splat(100.0)),
+        }
+    }
+}
+
+impl ExtractResource for GrassCoverage {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.clone()
+    }
+}
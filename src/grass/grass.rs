@@ -1,36 +1,74 @@
-use bevy::{prelude::*, render::{view::NoFrustumCulling, mesh::VertexAttributeValues, extract_component::ExtractComponent, render_resource::{Extent3d, TextureDimension, TextureFormat}}, utils::HashMap, ecs::query::QueryItem};
+use std::borrow::Cow;
+
+use bevy::{prelude::*, render::{view::NoFrustumCulling, mesh::VertexAttributeValues, extract_component::ExtractComponent, render_resource::TextureFormat}, utils::HashMap, ecs::query::QueryItem};
 #[cfg(feature = "bevy-inspector-egui")]
 use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
 
-use bytemuck::{Zeroable, Pod};
-use rand::Rng;
+use rand::{Rng, rngs::ThreadRng};
 
 use crate::render::instance::{GrassChunkData, GrassData};
 
-use super::{chunk::GrassChunks, config::GrassConfig};
+use super::{chunk::GrassChunks, config::GrassConfig, wind::Wind, material::{GrassMaterial, StandardGrassMaterial}};
+
+/// Grass drawn with the built-in shading.
+pub type GrassBundle = GrassMaterialBundle<StandardGrassMaterial>;
 
-#[derive(Bundle, Default)]
-pub struct GrassBundle {
+/// Grass drawn with a custom [`GrassMaterial`].
+#[derive(Bundle)]
+pub struct GrassMaterialBundle<M: GrassMaterial> {
     pub mesh: Handle<Mesh>,
     pub lod: GrassLODMesh,
     pub grass: Grass,
     pub grass_chunks: GrassChunks,
+    pub material: Handle<M>,
     #[bundle()]
     pub spatial: SpatialBundle,
     pub frustum_culling: NoFrustumCulling,
 }
 
+impl<M: GrassMaterial> Default for GrassMaterialBundle<M> {
+    fn default() -> Self {
+        Self {
+            mesh: Default::default(),
+            lod: Default::default(),
+            grass: Default::default(),
+            grass_chunks: Default::default(),
+            material: Default::default(),
+            spatial: Default::default(),
+            frustum_culling: NoFrustumCulling,
+        }
+    }
+}
+
+/// Generates the blades of grass that hasn't been generated yet, once its mesh and color map are loaded.
 pub fn generate_grass(
     mut query: Query<(&Grass, &mut GrassChunks)>,
     mesh_entity_query: Query<(&Transform, &Handle<Mesh>)>,
     meshes: Res<Assets<Mesh>>,
+    images: Res<Assets<Image>>,
+    mut grass_asset: ResMut<Assets<GrassChunkData>>,
     asset_server: Res<AssetServer>,
     config: Res<GrassConfig>,
 ) {
     for (grass, mut chunks) in query.iter_mut() {
-        let (transform, mesh_handle) = mesh_entity_query.get(grass.entity.unwrap()).unwrap();
-        let mesh = meshes.get(mesh_handle).unwrap();
-        chunks.chunks = grass.generate_grass(transform, mesh, chunks.chunk_size, &asset_server, &config);
+        if chunks.instances != Handle::default() {
+            continue;
+        }
+
+        let Some(Ok((transform, mesh_handle))) = grass.entity.map(|entity| mesh_entity_query.get(entity)) else {
+            continue;
+        };
+        let Some(mesh) = meshes.get(mesh_handle) else {
+            continue;
+        };
+        let loaded = |map: &Option<Handle<Image>>| map.iter().all(|handle| images.contains(handle));
+        if !loaded(&grass.color_map) || !loaded(&grass.density_map) {
+            continue;
+        }
+
+        let chunk_size = chunks.chunk_size;
+        chunks.chunks = grass.generate_grass(transform, mesh, &images, chunk_size, &asset_server, &config);
+        chunks.upload(grass.active_variants().len(), &mut grass_asset);
     }
 }
 
@@ -40,8 +78,23 @@ pub fn generate_grass(
 pub struct Grass {
     pub entity: Option<Entity>,
     pub density: u32,
+    /// Color of the blades when [`Grass::variants`] is empty.
     pub color: GrassColor,
+    /// Shape of the blades when [`Grass::variants`] is empty.
     pub blade: Blade,
+    /// Kinds of blades mixed in the field, each blade picks one at random by weight.
+    /// Only the first [`MAX_GRASS_VARIANTS`] are used.
+    pub variants: Vec<GrassVariant>,
+    /// Scales the weight of the variants with a [`GrassVariant::density_channel`] by the channel under the blade root.
+    /// Sampled like [`Grass::color_map`], blades are only placed where some variant has weight.
+    pub density_map: Option<Handle<Image>>,
+    /// Render the grass into the shadow maps of shadow casting lights. Every blade is drawn into every shadow map with
+    /// its high LOD mesh, also outside of the camera's view, so this is expensive for dense fields.
+    pub cast_shadows: bool,
+    pub shading: GrassShading,
+    /// Tints the blades with the color under their root, sampled through the UVs of the mesh when generating the grass.
+    /// Meshes without UVs stretch the map over their XZ bounds. Only 8 bit RGBA images are supported.
+    pub color_map: Option<Handle<Image>>,
 }
 
 impl Default for Grass {
@@ -51,81 +104,232 @@ impl Default for Grass {
             entity: None,
             color: GrassColor::default(),
             blade: Blade::default(),
+            variants: Vec::new(),
+            density_map: None,
+            cast_shadows: false,
+            shading: GrassShading::default(),
+            color_map: None,
         }
     }
 }
 
 impl Grass {
-    fn generate_grass(&self, transform: &Transform, mesh: &Mesh, chunk_size: f32, asset_server: &AssetServer, config: &GrassConfig) -> HashMap<(i32, i32, i32), GrassChunkData> {
-        let mut chunks: HashMap<(i32, i32, i32), GrassChunkData> = HashMap::new();
-
-        if let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
-            if let Some(indices) = mesh.indices() {
-                let mut triangle = Vec::new();
-                for index in indices.iter() {
-                    triangle.push(index);
-                    if triangle.len() == 3 {
-                        let _result: Vec<GrassData> = {
-                            let v0 = Vec3::from(positions[triangle[0] as usize]) * transform.scale;
-                            let v1 = Vec3::from(positions[triangle[1] as usize]) * transform.scale;
-                            let v2 = Vec3::from(positions[triangle[2] as usize]) * transform.scale;
-
-                            let normal = (v1 - v0).cross(v2 - v0).normalize();
-        
-                            let area = ((v1 - v0).cross(v2 - v0)).length() / 2.0;
-        
-                            let scaled_density = (self.density as f32 * area).ceil() as u32;
-        
-                            (0..scaled_density).filter_map(|_| {
-                                let mut rng = rand::thread_rng();
-        
-                                let r1 = rng.gen::<f32>().sqrt();
-                                let r2 = rng.gen::<f32>();
-                                let barycentric = Vec3::new(1.0 - r1, r1 * (1.0 - r2), r1 * r2);
-        
-                                let position = (v0 * barycentric.x + v1 * barycentric.y + v2 * barycentric.z) + transform.translation;
-                                
-                                let chunk_coords = (
-                                    (position.x / chunk_size).floor() as i32,
-                                    (position.y / chunk_size).floor() as i32,
-                                    (position.z / chunk_size).floor() as i32,
-                                );
-
-                                let chunk_base = Vec3::new(chunk_coords.0 as f32, chunk_coords.1 as f32, chunk_coords.2 as f32) * chunk_size;
-                                let chunk_pos = position - chunk_base;
-                                let chunk_uvw = Vec3::new(chunk_pos.x / chunk_size, chunk_pos.y / chunk_size, chunk_pos.z / chunk_size);
-                                
-                                let instance = GrassData {
-                                    position,
-                                    normal,
-                                    chunk_uvw,
-                                };
-
-                                chunks.entry(chunk_coords).or_insert_with(|| { GrassChunkData(Vec::new())}).0.push(instance);
-
-                                None
-                            }).collect::<Vec<_>>()
-                        };
-                        triangle.clear();
-                    }
+    /// The variants the blades are generated from, a single variant of [`Grass::color`] and [`Grass::blade`] when
+    /// [`Grass::variants`] is empty.
+    pub fn active_variants(&self) -> Cow<'_, [GrassVariant]> {
+        match self.variants.len() {
+            0 => Cow::Owned(vec![GrassVariant {
+                color: self.color,
+                blade: self.blade,
+                ..default()
+            }]),
+            len => Cow::Borrowed(&self.variants[..len.min(MAX_GRASS_VARIANTS)]),
+        }
+    }
+
+    fn generate_grass(&self, transform: &Transform, mesh: &Mesh, images: &Assets<Image>, chunk_size: f32, asset_server: &AssetServer, config: &GrassConfig) -> HashMap<(i32, i32, i32), Vec<GrassData>> {
+        let mut chunks: HashMap<(i32, i32, i32), Vec<GrassData>> = HashMap::new();
+
+        if self.variants.len() > MAX_GRASS_VARIANTS {
+            warn!("Grass has {} variants, only the first {} are used", self.variants.len(), MAX_GRASS_VARIANTS);
+        }
+        let variants = self.active_variants();
+        let color_map = self.color_map.as_ref().and_then(|handle| images.get(handle));
+        let density_map = self.density_map.as_ref().and_then(|handle| images.get(handle));
+
+        sample_mesh_surface(transform, mesh, self.density as f32, false, |point, rng| {
+            let tint = color_map.and_then(|image| {
+                let [r, g, b, _] = sample_image(image, point.uv)?;
+                Some(match image.texture_descriptor.format {
+                    TextureFormat::Rgba8UnormSrgb => Color::rgb(r, g, b),
+                    _ => Color::rgb_linear(r, g, b),
+                })
+            }).unwrap_or(Color::WHITE);
+
+            let density = density_map.and_then(|image| sample_image(image, point.uv));
+            let weights: Vec<f32> = variants.iter().map(|variant| {
+                match (variant.density_channel, density) {
+                    (Some(channel), Some(density)) => variant.weight * density[channel.min(3)],
+                    _ => variant.weight,
                 }
+            }).collect();
+            let total: f32 = weights.iter().sum();
+            if total <= 0.0 {
+                return;
             }
-        }
+            let mut pick = rng.gen::<f32>() * total;
+            let variant = weights.iter().position(|weight| {
+                pick -= weight;
+                pick < 0.0
+            }).unwrap_or(weights.len() - 1);
+
+            let (chunk_coords, chunk_uvw) = chunk_position(point.position, chunk_size);
+            let instance = GrassData::new(chunk_coords, chunk_uvw, point.normal, tint, rng.gen(), rng.gen(), variant as u8);
+
+            chunks.entry(chunk_coords).or_default().push(instance);
+        });
 
         chunks
     }
 }
 
+/// A random point on the surface of a mesh, in world space.
+pub(crate) struct SurfacePoint {
+    pub position: Vec3,
+    pub normal: Vec3,
+    /// UV of the mesh at the point, or the point over the XZ bounds of the mesh when it has no UVs.
+    pub uv: Vec2,
+}
+
+/// Calls `f` with `density` random points per unit of area of the mesh triangles, scaled and moved by `transform`.
+/// The point count of each triangle is rounded up, or rounded at random with `round_at_random`.
+pub(crate) fn sample_mesh_surface(transform: &Transform, mesh: &Mesh, density: f32, round_at_random: bool, mut f: impl FnMut(SurfacePoint, &mut ThreadRng)) {
+    let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) else {
+        return;
+    };
+    let Some(indices) = mesh.indices() else {
+        return;
+    };
+
+    let uvs = match mesh.attribute(Mesh::ATTRIBUTE_UV_0) {
+        Some(VertexAttributeValues::Float32x2(uvs)) => Some(uvs),
+        _ => None,
+    };
+    let xz_bounds = mesh.compute_aabb().map(|aabb| {
+        let min = Vec3::from(aabb.min()) * transform.scale + transform.translation;
+        let max = Vec3::from(aabb.max()) * transform.scale + transform.translation;
+        (min.xz(), max.xz())
+    });
+
+    let mut rng = rand::thread_rng();
+    let indices: Vec<usize> = indices.iter().collect();
+    for triangle in indices.chunks_exact(3) {
+        let v0 = Vec3::from(positions[triangle[0]]) * transform.scale;
+        let v1 = Vec3::from(positions[triangle[1]]) * transform.scale;
+        let v2 = Vec3::from(positions[triangle[2]]) * transform.scale;
+
+        let normal = (v1 - v0).cross(v2 - v0).normalize();
+        let area = ((v1 - v0).cross(v2 - v0)).length() / 2.0;
+
+        // rounding at random lets sparse densities average out over small triangles instead of placing at least one
+        let count = if round_at_random {
+            (density * area + rng.gen::<f32>()).floor() as u32
+        } else {
+            (density * area).ceil() as u32
+        };
+        for _ in 0..count {
+            let r1 = rng.gen::<f32>().sqrt();
+            let r2 = rng.gen::<f32>();
+            let barycentric = Vec3::new(1.0 - r1, r1 * (1.0 - r2), r1 * r2);
+
+            let position = (v0 * barycentric.x + v1 * barycentric.y + v2 * barycentric.z) + transform.translation;
+            let uv = match (uvs, xz_bounds) {
+                (Some(uvs), _) => {
+                    let uv = |i: usize| Vec2::from(uvs[triangle[i]]);
+                    uv(0) * barycentric.x + uv(1) * barycentric.y + uv(2) * barycentric.z
+                }
+                (None, Some((min, max))) => (position.xz() - min) / (max - min),
+                (None, None) => Vec2::ZERO,
+            };
+
+            f(SurfacePoint { position, normal, uv }, &mut rng);
+        }
+    }
+}
+
+/// The chunk containing a world position and the position inside it, from 0 to 1 on each axis.
+pub(crate) fn chunk_position(position: Vec3, chunk_size: f32) -> ((i32, i32, i32), Vec3) {
+    let chunk = (position / chunk_size).floor();
+    let chunk_uvw = position / chunk_size - chunk;
+    ((chunk.x as i32, chunk.y as i32, chunk.z as i32), chunk_uvw)
+}
+
+/// Nearest sample of an 8 bit RGBA image, the raw channels without color space conversion.
+/// Other formats return `None`.
+fn sample_image(image: &Image, uv: Vec2) -> Option<[f32; 4]> {
+    if !matches!(image.texture_descriptor.format, TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm) {
+        return None;
+    }
+
+    let size = image.size();
+    let x = ((uv.x.fract() + 1.0).fract() * size.x as f32) as usize;
+    let y = ((uv.y.fract() + 1.0).fract() * size.y as f32) as usize;
+    let i = (y.min(size.y as usize - 1) * size.x as usize + x.min(size.x as usize - 1)) * 4;
+
+    image.data.get(i..i + 4).map(|texel| [0, 1, 2, 3].map(|channel| texel[channel] as f32 / 255.0))
+}
+
 impl ExtractComponent for Grass {
     type Query = &'static Grass;
     type Filter = ();
-    type Out = (GrassColor, Blade);
+    type Out = (RenderGrassVariants, GrassShadowCaster, GrassShading);
 
     fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self::Out> {
-        Some((item.color.clone(), item.blade.clone()))
+        let variants = item.active_variants();
+        let variants = RenderGrassVariants {
+            colors: variants.iter().map(|variant| variant.color).collect(),
+            blades: variants.iter().map(|variant| variant.blade).collect(),
+            meshes: variants.iter().map(|variant| variant.mesh.as_ref().map(Handle::id)).collect(),
+        };
+        Some((variants, GrassShadowCaster(item.cast_shadows), item.shading))
+    }
+}
+
+/// Most variants a [`Grass`] can have, the size of the blade and color uniform arrays.
+pub const MAX_GRASS_VARIANTS: usize = 8;
+
+/// One kind of blade in a [`Grass`] field, like short fine grass, tall wild grass or reeds.
+#[derive(Clone)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
+#[cfg_attr(feature = "bevy-inspector-egui", reflect(InspectorOptions))]
+pub struct GrassVariant {
+    /// Chance of a blade being this variant, relative to the other variants.
+    pub weight: f32,
+    pub color: GrassColor,
+    pub blade: Blade,
+    /// Blade mesh of this variant at every LOD, it needs the same vertex attributes as the mesh of the grass entity.
+    /// Defaults to the mesh of the grass entity and its [`GrassLODMesh`].
+    pub mesh: Option<Handle<Mesh>>,
+    /// Channel (0 to 3, RGBA) of [`Grass::density_map`] scaling the weight of this variant.
+    pub density_channel: Option<usize>,
+}
+
+impl Default for GrassVariant {
+    fn default() -> Self {
+        Self {
+            weight: 1.0,
+            color: GrassColor::default(),
+            blade: Blade::default(),
+            mesh: None,
+            density_channel: None,
+        }
     }
 }
 
+/// The colors, blades and meshes of the [`GrassVariant`]s, extracted from [`Grass`].
+#[derive(Component, Clone)]
+pub struct RenderGrassVariants {
+    pub colors: Vec<GrassColor>,
+    pub blades: Vec<Blade>,
+    pub meshes: Vec<Option<AssetId<Mesh>>>,
+}
+
+/// Whether the grass is queued into the shadow passes, extracted from [`Grass::cast_shadows`].
+#[derive(Component, Clone, Copy)]
+pub struct GrassShadowCaster(pub bool);
+
+/// How the grass fragment shader lights the blades.
+#[derive(Component, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect))]
+pub enum GrassShading {
+    /// The stylized two tone gradient lit by the scene lights.
+    #[default]
+    Stylized,
+    /// Bevy's PBR lighting, including ambient light, environment maps and diffuse transmission,
+    /// using the roughness, reflectance and translucency from [`Blade`].
+    Pbr,
+}
+
 #[derive(Component, Clone, Copy)]
 #[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
 #[cfg_attr(feature = "bevy-inspector-egui", reflect(InspectorOptions))]
@@ -155,10 +359,9 @@ impl Default for GrassColor {
     }
 }
 
-#[derive(Component, Clone, Copy, Pod, Zeroable)]
+#[derive(Component, Clone, Copy)]
 #[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
 #[cfg_attr(feature = "bevy-inspector-egui", reflect(InspectorOptions))]
-#[repr(C)]
 pub struct Blade {
     pub length: f32,
     pub width: f32,
@@ -168,6 +371,46 @@ pub struct Blade {
     pub p2_flexibility: f32,
     pub curve: f32,
     pub specular: f32,
+    pub roughness: f32,
+    pub reflectance: f32,
+    pub translucency: f32,
+    pub thickness: f32,
+    /// Each blade is up to this fraction longer than [`Blade::length`].
+    pub length_variance: f32,
+    /// Each blade is up to this fraction wider than [`Blade::width`].
+    pub width_variance: f32,
+    /// Added at random, up to this much, to [`Blade::p1_flexibility`] and [`Blade::p2_flexibility`].
+    pub flexibility_variance: f32,
+    /// Added at random, up to this many degrees, to [`Blade::curve`].
+    pub curve_variance: f32,
+    /// Most degrees the hue of a blade is shifted either way.
+    pub hue_variance: f32,
+    /// Most a blade is brightened or darkened, as a fraction of its color.
+    pub brightness_variance: f32,
+    pub facing: BladeFacing,
+}
+
+impl Blade {
+    /// Furthest distance a blade can reach from its root, including length variation and wind sway.
+    pub fn max_reach(&self, wind: &Wind) -> f32 {
+        self.length * (1.0 + self.length_variance.max(0.0))
+            + 0.5 * wind.amplitude.abs()
+            + wind.oscillation.abs()
+            + self.width * (1.0 + self.width_variance.max(0.0))
+    }
+}
+
+/// Which way the blades lean and turn their flat side.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect))]
+pub enum BladeFacing {
+    /// Each blade faces a random direction.
+    #[default]
+    Random,
+    /// Every blade faces the same direction, in degrees around the up axis like [`Wind::direction`].
+    Direction(f32),
+    /// The blades turn towards the camera with the lowest order, so the field never shows their thin side.
+    Camera,
 }
 
 impl Default for Blade {
@@ -181,6 +424,17 @@ impl Default for Blade {
             p2_flexibility: 0.5,
             curve: 15.,
             specular: 0.02,
+            roughness: 0.6,
+            reflectance: 0.3,
+            translucency: 0.4,
+            thickness: 0.05,
+            length_variance: 0.5,
+            width_variance: 0.0,
+            flexibility_variance: 0.2,
+            curve_variance: 0.0,
+            hue_variance: 0.0,
+            brightness_variance: 0.0,
+            facing: BladeFacing::Random,
         }
     }
 }
@@ -0,0 +1,260 @@
+use std::{hash::Hash, marker::PhantomData};
+
+use bevy::{prelude::*, render::{render_resource::{AsBindGroup, AsBindGroupShaderType, ShaderRef, ShaderType, ShaderDefVal, FragmentState, RenderPipelineDescriptor, SpecializedMeshPipelineError, SpecializedMeshPipelines}, mesh::MeshVertexBufferLayout, extract_component::ExtractComponentPlugin, render_asset::{prepare_assets, RenderAssets}, render_phase::AddRenderCommand, RenderApp, Render, RenderSet, ExtractSchedule}, core_pipeline::{core_3d::Opaque3d, prepass::Opaque3dPrepass, deferred::Opaque3dDeferred}, pbr::Shadow};
+
+pub use crate::render::pipeline::GrassPipelineKey;
+use crate::GRASS_SHADER_HANDLE;
+use crate::render::{pipeline::{GrassPipeline, GrassMaterialPipeline}, draw::{DrawGrass, DrawGrassPrepass, DrawGrassShadow}, material::{ExtractedGrassMaterials, RenderGrassMaterials}};
+
+/// Custom shaders, uniforms and textures for grass, the grass counterpart of Bevy's [`Material`].
+///
+/// The material bind group is bound at group 1, the grass uniforms stay at group 2 and the wind at group 3.
+/// Stages that aren't overridden use the built-in grass shader, which ignores the material.
+/// Register each material with a [`GrassMaterialPlugin`] and spawn it with a [`GrassMaterialBundle`](super::grass::GrassMaterialBundle).
+pub trait GrassMaterial: Asset + AsBindGroup + Clone + Sized {
+    /// Vertex shader of the main pass, the built-in one places and sways the blades.
+    fn vertex_shader() -> ShaderRef {
+        ShaderRef::Default
+    }
+
+    /// Fragment shader of the main pass, it receives the `VertexOutput` of the vertex shader.
+    fn fragment_shader() -> ShaderRef {
+        ShaderRef::Default
+    }
+
+    /// Vertex shader of the prepass, deferred and shadow passes, compiled with `PREPASS_PIPELINE`.
+    fn prepass_vertex_shader() -> ShaderRef {
+        ShaderRef::Default
+    }
+
+    /// Fragment shader of the prepass, deferred and shadow passes, compiled with `PREPASS_PIPELINE`.
+    fn prepass_fragment_shader() -> ShaderRef {
+        ShaderRef::Default
+    }
+
+    /// Customizes the pipeline once the grass pipeline has specialized it.
+    #[allow(unused_variables)]
+    #[inline]
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayout,
+        key: GrassMaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        Ok(())
+    }
+}
+
+/// How the alpha of the [`StandardGrassMaterial`] textures cuts out the blade.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum GrassAlphaMode {
+    #[default]
+    Opaque,
+    /// Discards the parts of the blade with an alpha below the cutoff.
+    Mask(f32),
+    /// Smooth edges with MSAA. Falls back to a 0.5 mask without MSAA and in the prepass and shadow passes.
+    AlphaToCoverage,
+}
+
+/// The built-in grass shading, optionally textured along the blade UVs of [`GrassMesh`](super::mesh::GrassMesh).
+/// The textures are multiplied with the [`GrassColor`](super::grass::GrassColor) gradient, with the blade tip at the top.
+#[derive(Asset, AsBindGroup, TypePath, Clone)]
+#[bind_group_data(StandardGrassMaterialKey)]
+#[uniform(0, StandardGrassMaterialUniform)]
+pub struct StandardGrassMaterial {
+    #[texture(1)]
+    #[sampler(2)]
+    pub albedo_texture: Option<Handle<Image>>,
+    /// Tangent space normal map, y points towards the blade tip.
+    #[texture(3)]
+    #[sampler(4)]
+    pub normal_texture: Option<Handle<Image>>,
+    /// Alpha in the red channel, replaces the alpha of the albedo texture.
+    #[texture(5)]
+    #[sampler(6)]
+    pub alpha_texture: Option<Handle<Image>>,
+    /// The textures hold this many blade variations side by side, each blade samples one at random.
+    pub texture_columns: u32,
+    pub alpha_mode: GrassAlphaMode,
+}
+
+impl Default for StandardGrassMaterial {
+    fn default() -> Self {
+        Self {
+            albedo_texture: None,
+            normal_texture: None,
+            alpha_texture: None,
+            texture_columns: 1,
+            alpha_mode: GrassAlphaMode::Opaque,
+        }
+    }
+}
+
+#[derive(Clone, ShaderType)]
+pub struct StandardGrassMaterialUniform {
+    pub alpha_cutoff: f32,
+    pub texture_columns: u32,
+}
+
+impl AsBindGroupShaderType<StandardGrassMaterialUniform> for StandardGrassMaterial {
+    fn as_bind_group_shader_type(&self, _images: &RenderAssets<Image>) -> StandardGrassMaterialUniform {
+        StandardGrassMaterialUniform {
+            alpha_cutoff: match self.alpha_mode {
+                GrassAlphaMode::Mask(cutoff) => cutoff,
+                _ => 0.5,
+            },
+            texture_columns: self.texture_columns,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct StandardGrassMaterialKey {
+    albedo_texture: bool,
+    normal_texture: bool,
+    alpha_texture: bool,
+    alpha_mask: bool,
+    alpha_to_coverage: bool,
+}
+
+impl From<&StandardGrassMaterial> for StandardGrassMaterialKey {
+    fn from(material: &StandardGrassMaterial) -> Self {
+        Self {
+            albedo_texture: material.albedo_texture.is_some(),
+            normal_texture: material.normal_texture.is_some(),
+            alpha_texture: material.alpha_texture.is_some(),
+            alpha_mask: material.alpha_mode != GrassAlphaMode::Opaque,
+            alpha_to_coverage: material.alpha_mode == GrassAlphaMode::AlphaToCoverage,
+        }
+    }
+}
+
+impl GrassMaterial for StandardGrassMaterial {
+    fn specialize(
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        key: GrassMaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        let material_key = key.bind_group_data;
+        let mut shader_defs: Vec<ShaderDefVal> = vec!["STANDARD_GRASS_MATERIAL".into()];
+        if material_key.albedo_texture {
+            shader_defs.push("GRASS_ALBEDO_TEXTURE".into());
+        }
+        if material_key.normal_texture {
+            shader_defs.push("GRASS_NORMAL_TEXTURE".into());
+        }
+        if material_key.alpha_texture {
+            shader_defs.push("GRASS_ALPHA_TEXTURE".into());
+        }
+        if material_key.alpha_mask {
+            shader_defs.push("GRASS_ALPHA_MASK".into());
+        }
+        if material_key.alpha_to_coverage && !key.grass_key.prepass && key.grass_key.mesh_key.msaa_samples() > 1 {
+            shader_defs.push("GRASS_ALPHA_TO_COVERAGE".into());
+            descriptor.multisample.alpha_to_coverage_enabled = true;
+        }
+
+        descriptor.vertex.shader_defs.extend(shader_defs.iter().cloned());
+        match descriptor.fragment.as_mut() {
+            Some(fragment) => fragment.shader_defs.extend(shader_defs),
+            // depth only passes need a fragment stage to discard the masked parts of the blade
+            None if material_key.alpha_mask => {
+                descriptor.fragment = Some(FragmentState {
+                    shader: GRASS_SHADER_HANDLE,
+                    shader_defs: descriptor.vertex.shader_defs.clone(),
+                    entry_point: "fragment".into(),
+                    targets: Vec::new(),
+                });
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+}
+
+pub struct GrassMaterialPipelineKey<M: GrassMaterial> {
+    pub grass_key: GrassPipelineKey,
+    pub bind_group_data: M::Data,
+}
+
+impl<M: GrassMaterial> Clone for GrassMaterialPipelineKey<M>
+where
+    M::Data: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            grass_key: self.grass_key,
+            bind_group_data: self.bind_group_data.clone(),
+        }
+    }
+}
+
+impl<M: GrassMaterial> PartialEq for GrassMaterialPipelineKey<M>
+where
+    M::Data: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.grass_key == other.grass_key && self.bind_group_data == other.bind_group_data
+    }
+}
+
+impl<M: GrassMaterial> Eq for GrassMaterialPipelineKey<M> where M::Data: Eq {}
+
+impl<M: GrassMaterial> Hash for GrassMaterialPipelineKey<M>
+where
+    M::Data: Hash,
+{
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.grass_key.hash(state);
+        self.bind_group_data.hash(state);
+    }
+}
+
+/// Prepares and draws the grass using the material `M`.
+/// [`ProceduralGrassPlugin`](crate::ProceduralGrassPlugin) already adds it for [`StandardGrassMaterial`].
+pub struct GrassMaterialPlugin<M: GrassMaterial>(PhantomData<M>);
+
+impl<M: GrassMaterial> Default for GrassMaterialPlugin<M> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<M: GrassMaterial> Plugin for GrassMaterialPlugin<M>
+where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    fn build(&self, app: &mut App) {
+        app
+            .init_asset::<M>()
+            .add_plugins(ExtractComponentPlugin::<Handle<M>>::default());
+
+        app.sub_app_mut(RenderApp)
+            .add_render_command::<Opaque3d, DrawGrass<M>>()
+            .add_render_command::<Opaque3dPrepass, DrawGrassPrepass<M>>()
+            .add_render_command::<Opaque3dDeferred, DrawGrassPrepass<M>>()
+            .add_render_command::<Shadow, DrawGrassShadow<M>>()
+            .init_resource::<SpecializedMeshPipelines<GrassMaterialPipeline<M>>>()
+            .init_resource::<ExtractedGrassMaterials<M>>()
+            .init_resource::<RenderGrassMaterials<M>>()
+            .add_systems(ExtractSchedule, crate::render::material::extract_grass_materials::<M>)
+            .add_systems(
+                Render,
+                (
+                    crate::render::material::prepare_grass_materials::<M>
+                        .in_set(RenderSet::PrepareAssets)
+                        .after(prepare_assets::<Image>),
+                    crate::render::queue::grass_queue::<M>.in_set(RenderSet::QueueMeshes),
+                    crate::render::queue::grass_queue_prepass::<M>.in_set(RenderSet::QueueMeshes),
+                    crate::render::queue::grass_queue_shadows::<M>.in_set(RenderSet::QueueMeshes),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        // material plugins can finish before the grass plugin that adds them
+        app.sub_app_mut(RenderApp)
+            .init_resource::<GrassPipeline>()
+            .init_resource::<GrassMaterialPipeline<M>>();
+    }
+}
@@ -2,4 +2,8 @@ pub mod grass;
 pub mod wind;
 pub mod chunk;
 pub mod mesh;
-pub mod config;
\ No newline at end of file
+pub mod config;
+pub mod material;
+pub mod scatter;
+pub mod season;
+pub mod coverage;
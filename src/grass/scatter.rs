@@ -0,0 +1,144 @@
+use bevy::{prelude::*, render::{view::NoFrustumCulling, extract_component::ExtractComponent, batching::NoAutomaticBatching, primitives::Frustum}, utils::HashMap, ecs::query::QueryItem};
+#[cfg(feature = "bevy-inspector-egui")]
+use bevy_inspector_egui::{prelude::ReflectInspectorOptions, InspectorOptions};
+use rand::Rng;
+
+use crate::render::instance::{GrassChunkData, GrassData};
+
+use super::{chunk::{GrassChunks, cull_chunks}, grass::{sample_mesh_surface, chunk_position}, config::GrassConfig, wind::{GrassWind, WindReach}};
+
+/// Scatters copies of a mesh, like flowers, pebbles or mushrooms, over the surface of [`Scatter::entity`].
+/// The instances are placed, chunked and culled like grass blades and drawn with a [`StandardMaterial`].
+#[derive(Bundle)]
+pub struct ScatterBundle {
+    /// The mesh drawn at every instance.
+    pub mesh: Handle<Mesh>,
+    pub scatter: Scatter,
+    pub grass_chunks: GrassChunks,
+    #[bundle()]
+    pub spatial: SpatialBundle,
+    pub frustum_culling: NoFrustumCulling,
+    /// Keeps scatters sharing a mesh from being merged into one draw.
+    pub no_automatic_batching: NoAutomaticBatching,
+}
+
+impl Default for ScatterBundle {
+    fn default() -> Self {
+        Self {
+            mesh: Default::default(),
+            scatter: Default::default(),
+            grass_chunks: Default::default(),
+            spatial: Default::default(),
+            frustum_culling: NoFrustumCulling,
+            no_automatic_batching: NoAutomaticBatching,
+        }
+    }
+}
+
+#[derive(Component, Clone)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
+#[cfg_attr(feature = "bevy-inspector-egui", reflect(InspectorOptions))]
+pub struct Scatter {
+    /// The entity whose mesh the instances are scattered over.
+    pub entity: Option<Entity>,
+    /// Instances per unit of area.
+    pub density: f32,
+    /// Kept here rather than on the entity, so Bevy doesn't draw the mesh itself.
+    pub material: Handle<StandardMaterial>,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Turns each instance by a random angle around its up axis.
+    pub random_rotation: bool,
+    /// Tilts the instances to the surface normal instead of keeping them upright.
+    pub align_to_normal: bool,
+    /// How far the wind bends the instances, relative to the sway of the grass blades. The bend grows with the
+    /// height above the root, so 0 keeps rocks still and small values give flowers a gentle sway.
+    pub sway: f32,
+}
+
+impl Default for Scatter {
+    fn default() -> Self {
+        Self {
+            entity: None,
+            density: 0.5,
+            material: Default::default(),
+            min_scale: 0.8,
+            max_scale: 1.2,
+            random_rotation: true,
+            align_to_normal: false,
+            sway: 0.0,
+        }
+    }
+}
+
+impl Scatter {
+    fn generate_scatter(&self, transform: &Transform, mesh: &Mesh, chunk_size: f32) -> HashMap<(i32, i32, i32), Vec<GrassData>> {
+        let mut chunks: HashMap<(i32, i32, i32), Vec<GrassData>> = HashMap::new();
+
+        sample_mesh_surface(transform, mesh, self.density, true, |point, rng| {
+            let (chunk_coords, chunk_uvw) = chunk_position(point.position, chunk_size);
+            // the height scale of the blades is the random scale of the instance
+            let instance = GrassData::new(chunk_coords, chunk_uvw, point.normal, Color::WHITE, rng.gen(), rng.gen(), 0);
+
+            chunks.entry(chunk_coords).or_default().push(instance);
+        });
+
+        chunks
+    }
+}
+
+/// Generates the instances of scatters that haven't been generated yet, once the surface mesh is loaded.
+pub fn generate_scatter(
+    mut query: Query<(&Scatter, &mut GrassChunks)>,
+    mesh_entity_query: Query<(&Transform, &Handle<Mesh>)>,
+    meshes: Res<Assets<Mesh>>,
+    mut grass_asset: ResMut<Assets<GrassChunkData>>,
+) {
+    for (scatter, mut chunks) in query.iter_mut() {
+        if chunks.instances != Handle::default() {
+            continue;
+        }
+
+        let Some(Ok((transform, mesh_handle))) = scatter.entity.map(|entity| mesh_entity_query.get(entity)) else {
+            continue;
+        };
+        let Some(mesh) = meshes.get(mesh_handle) else {
+            continue;
+        };
+
+        let chunk_size = chunks.chunk_size;
+        chunks.chunks = scatter.generate_scatter(transform, mesh, chunk_size);
+        chunks.upload(1, &mut grass_asset);
+    }
+}
+
+pub(crate) fn scatter_culling(
+    mut query: Query<(&Scatter, &Handle<Mesh>, &mut GrassChunks, Option<&GrassWind>)>,
+    meshes: Res<Assets<Mesh>>,
+    camera_query: Query<(&Transform, &Frustum)>,
+    grass_config: Res<GrassConfig>,
+    wind_reach: WindReach,
+) {
+    for (scatter, mesh_handle, mut chunks, local_wind) in query.iter_mut() {
+        let Some(aabb) = meshes.get(mesh_handle).and_then(Mesh::compute_aabb) else {
+            continue;
+        };
+        let wind = wind_reach.strongest_wind(local_wind);
+
+        // the instances can turn and tilt in any direction, and the wind bends them by up to half its amplitude plus the
+        // gust and impulse push per unit of height
+        let radius = (aabb.center.abs() + aabb.half_extents).length() * scatter.min_scale.abs().max(scatter.max_scale.abs());
+        let reach = radius * (1.0 + (0.5 * wind.amplitude.abs() + wind_reach.push()) * scatter.sway.abs());
+        cull_chunks(&mut chunks, reach, &camera_query, &grass_config);
+    }
+}
+
+impl ExtractComponent for Scatter {
+    type Query = &'static Scatter;
+    type Filter = ();
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self::Out> {
+        Some(item.clone())
+    }
+}
@@ -0,0 +1,38 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+#[cfg(feature = "bevy-inspector-egui")]
+use bevy_inspector_egui::{InspectorOptions, inspector_options::ReflectInspectorOptions};
+
+/// Shifts the color of every grass field along a color ramp, e.g. from green in spring to yellow in autumn and to
+/// darker tones after rain. Changing it only rewrites a uniform, the grass isn't regenerated.
+#[derive(Resource, Clone)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
+#[cfg_attr(feature = "bevy-inspector-egui", reflect(Resource, InspectorOptions))]
+pub struct GrassSeason {
+    /// Position along the ramp, from 0 at its left edge to 1 at its right edge.
+    pub value: f32,
+    /// Each blade samples the ramp up to this far either side of [`GrassSeason::value`], so a change spreads
+    /// over the field instead of turning every blade at once.
+    pub variance: f32,
+    /// Gradient texture, sampled across by [`GrassSeason::value`] and from the blade root at the bottom to the tip at
+    /// the top, so a one pixel high ramp colors the whole blade. Its alpha blends it over the
+    /// [`GrassColor`](super::grass::GrassColor) of the blades, no ramp leaves them unchanged.
+    pub ramp: Option<Handle<Image>>,
+}
+
+impl Default for GrassSeason {
+    fn default() -> Self {
+        Self {
+            value: 0.0,
+            variance: 0.05,
+            ramp: None,
+        }
+    }
+}
+
+impl ExtractResource for GrassSeason {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.clone()
+    }
+}
@@ -1,8 +1,11 @@
 use bevy::ecs::query::QueryItem;
+use bevy::ecs::system::SystemParam;
 use bevy::render::extract_component::ExtractComponent;
 use bevy::render::extract_resource::ExtractResource;
+use bevy::render::color::SrgbColorSpace;
 use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 use bevy::prelude::*;
+use bevy::tasks::AsyncComputeTaskPool;
 #[cfg(feature = "bevy-inspector-egui")]
 use bevy_inspector_egui::{InspectorOptions, prelude::ReflectInspectorOptions};
 use bytemuck::{Pod, Zeroable};
@@ -46,12 +49,12 @@ pub struct GrassWind {
 }
 
 impl ExtractComponent for GrassWind {
-    type Query = &'static GrassWind;
+    type Query = (&'static GrassWind, Option<&'static PreviousWind>);
     type Filter = ();
-    type Out = Self;
+    type Out = (Self, PreviousWind);
 
-    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self::Out> {
-        Some(item.clone())
+    fn extract_component((wind, previous_wind): QueryItem<'_, Self::Query>) -> Option<Self::Out> {
+        Some((wind.clone(), previous_wind.copied().unwrap_or(PreviousWind(wind.wind_data))))
     }
 }
 
@@ -63,58 +66,789 @@ impl ExtractResource for GrassWind {
     }
 }
 
-use noise::NoiseFn;
+/// The [`Wind`] of the previous frame, used to compute the motion vectors of the swaying blades.
+#[derive(Component, Resource, Default, Clone, Copy)]
+pub struct PreviousWind(pub Wind);
+
+impl ExtractResource for PreviousWind {
+    type Source = Self;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        *source
+    }
+}
+
+pub(crate) fn update_previous_wind(
+    mut commands: Commands,
+    wind: Res<GrassWind>,
+    mut previous_wind: ResMut<PreviousWind>,
+    mut local_winds: Query<(Entity, &GrassWind, Option<&mut PreviousWind>)>,
+) {
+    previous_wind.0 = wind.wind_data;
+
+    for (entity, grass_wind, previous_wind) in &mut local_winds {
+        match previous_wind {
+            Some(mut previous_wind) => previous_wind.0 = grass_wind.wind_data,
+            None => {
+                commands.entity(entity).insert(PreviousWind(grass_wind.wind_data));
+            }
+        }
+    }
+}
+
+/// Gust fronts traveling across every grass field with the wind, pushing the blades further as they pass.
+/// The gusts slowly turn around the direction of the global [`GrassWind`], their current state is readable with
+/// [`WindGusts::direction`] and [`WindGusts::sway`].
+#[derive(Resource, Clone)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
+#[cfg_attr(feature = "bevy-inspector-egui", reflect(Resource, InspectorOptions))]
+pub struct WindGusts {
+    /// Gust fronts passing a point per second.
+    pub frequency: f32,
+    /// How far a gust pushes the blade tips at the middle of its front, 0 disables the gusts.
+    pub strength: f32,
+    /// Width of a gust front across its direction of travel.
+    pub width: f32,
+    /// Units per second the fronts travel.
+    pub speed: f32,
+    /// Most the gusts turn away from the wind direction, in degrees.
+    pub direction_variance: f32,
+    /// How quickly the gusts turn, in turns per second.
+    pub turn_speed: f32,
+    #[cfg_attr(feature = "bevy-inspector-egui", reflect(ignore))]
+    state: GustState,
+}
+
+#[derive(Clone, Copy, Default)]
+struct GustState {
+    elapsed: f32,
+    /// Degrees, like [`Wind::direction`].
+    direction: f32,
+    /// How far the fronts have traveled.
+    offset: f32,
+}
+
+impl Default for WindGusts {
+    fn default() -> Self {
+        Self {
+            frequency: 0.1,
+            strength: 0.0,
+            width: 8.0,
+            speed: 6.0,
+            direction_variance: 30.0,
+            turn_speed: 0.01,
+            state: GustState::default(),
+        }
+    }
+}
+
+impl WindGusts {
+    /// The current direction of the gusts, in degrees like [`Wind::direction`].
+    pub fn direction(&self) -> f32 {
+        self.state.direction
+    }
+
+    /// How far the fronts have traveled, wrapped to the [`WindGusts::spacing`] between them.
+    pub fn offset(&self) -> f32 {
+        self.state.offset
+    }
+
+    /// Distance between two fronts.
+    pub fn spacing(&self) -> f32 {
+        if self.frequency > 0.0 {
+            self.speed.abs() / self.frequency
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    /// Unit vector the fronts travel along, the wind blows the blades towards the opposite of its direction.
+    pub fn travel_direction(&self) -> Vec2 {
+        -Vec2::from_angle(self.state.direction.to_radians())
+    }
+
+    /// How strongly a front pushes at `position` right now, from 0 between the fronts to 1 at their middle.
+    pub fn intensity(&self, position: Vec3) -> f32 {
+        let spacing = self.spacing();
+        if !spacing.is_finite() || spacing <= 0.0 || self.width <= 0.0 {
+            return 0.0;
+        }
+
+        let distance = position.xz().dot(self.travel_direction()) - self.state.offset;
+        let to_front = ((distance / spacing + 0.5).rem_euclid(1.0) - 0.5).abs() * spacing;
+        1.0 - smoothstep(0.0, self.width * 0.5, to_front)
+    }
+
+    /// How far the gusts push a blade tip at `position` right now, on top of the [`Wind`].
+    pub fn sway(&self, position: Vec3) -> Vec2 {
+        self.travel_direction() * self.strength * self.intensity(position)
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Moves the gust fronts forward and turns them around the wind direction.
+pub(crate) fn update_wind_gusts(
+    mut gusts: ResMut<WindGusts>,
+    wind: Res<GrassWind>,
+    time: Res<Time>,
+) {
+    let gusts = gusts.as_mut();
+    let delta = time.delta_seconds();
+    gusts.state.elapsed += delta;
+    gusts.state.offset += delta * gusts.speed.abs();
+    let spacing = gusts.spacing();
+    if spacing.is_finite() && spacing > 0.0 {
+        gusts.state.offset = gusts.state.offset.rem_euclid(spacing);
+    }
+
+    // two incommensurate waves, so the turning doesn't visibly repeat
+    let phase = gusts.state.elapsed * gusts.turn_speed * std::f32::consts::TAU;
+    let wander = (phase.sin() + 0.5 * (2.3 * phase + 1.7).sin()) / 1.5;
+    gusts.state.direction = wind.wind_data.direction + wander * gusts.direction_variance;
+}
+
+/// Most [`WindZone`]s blended into the wind, further zones are ignored.
+pub const MAX_WIND_ZONES: usize = 16;
+
+/// A volume with its own [`Wind`], e.g. a sheltered valley next to a windy ridge. The grass inside it sways with the
+/// zone's wind instead of the [`GrassWind`] of the field, positioned, turned and scaled by the entity's transform.
+/// Overlapping zones are blended in no particular order.
+#[derive(Component, Clone)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
+#[cfg_attr(feature = "bevy-inspector-egui", reflect(InspectorOptions))]
+pub struct WindZone {
+    pub shape: WindZoneShape,
+    /// Distance outside the shape over which the zone fades out, in the zone's local space.
+    pub falloff: f32,
+    /// How much the zone replaces the wind inside its shape, 1 overrides it and smaller values blend with it.
+    pub blend: f32,
+    /// Sways the blades through the wind map of the field they belong to.
+    pub wind: Wind,
+}
+
+impl Default for WindZone {
+    fn default() -> Self {
+        Self {
+            shape: WindZoneShape::default(),
+            falloff: 5.0,
+            blend: 1.0,
+            wind: Wind::default(),
+        }
+    }
+}
+
+impl WindZone {
+    /// How much the zone replaces the wind at `position`, from 0 outside its falloff up to its blend.
+    pub fn weight(&self, transform: &GlobalTransform, position: Vec3) -> f32 {
+        let p = transform.affine().inverse().transform_point3(position);
+        // distance outside the shape
+        let d = match self.shape {
+            WindZoneShape::Sphere { radius } => p.length() - radius,
+            WindZoneShape::Box { half_extents } => (p.abs() - half_extents).max(Vec3::ZERO).length(),
+        };
+        self.blend * (1.0 - smoothstep(0.0, self.falloff.max(0.0001), d))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect))]
+pub enum WindZoneShape {
+    Sphere { radius: f32 },
+    Box { half_extents: Vec3 },
+}
+
+impl Default for WindZoneShape {
+    fn default() -> Self {
+        Self::Sphere { radius: 10.0 }
+    }
+}
+
+#[derive(Bundle, Default)]
+pub struct WindZoneBundle {
+    pub wind_zone: WindZone,
+    #[bundle()]
+    pub transform: TransformBundle,
+}
+
+/// Most [`WindImpulse`]s pushing the grass at once, further impulses are ignored.
+pub const MAX_WIND_IMPULSES: usize = 16;
+
+/// Pushes the grass around the entity outward, like the blast of an explosion or the downwash under a helicopter.
+/// The push is added on top of the wind within [`WindImpulse::radius`] of the entity, horizontally.
+#[derive(Component, Clone)]
+#[cfg_attr(feature = "bevy-inspector-egui", derive(Reflect, InspectorOptions))]
+#[cfg_attr(feature = "bevy-inspector-egui", reflect(InspectorOptions))]
+pub struct WindImpulse {
+    pub radius: f32,
+    /// How far the blade tips are pushed, fading out over the duration.
+    pub strength: f32,
+    /// Seconds until the impulse is removed from the entity, or the entity despawned with [`DespawnFinishedImpulse`].
+    /// `None` pushes until it's removed.
+    pub duration: Option<f32>,
+    /// Width of the fade out at the edge of the radius, and of the front of an expanding ring.
+    pub falloff: f32,
+    /// Speed of a ring expanding from the center up to the radius, like a shockwave. 0 pushes the whole circle at once.
+    pub expansion_speed: f32,
+    #[cfg_attr(feature = "bevy-inspector-egui", reflect(ignore))]
+    elapsed: f32,
+}
+
+impl Default for WindImpulse {
+    fn default() -> Self {
+        Self {
+            radius: 10.0,
+            strength: 1.0,
+            duration: Some(1.0),
+            falloff: 2.0,
+            expansion_speed: 0.0,
+            elapsed: 0.0,
+        }
+    }
+}
+
+impl WindImpulse {
+    /// Seconds since the impulse started.
+    pub fn elapsed(&self) -> f32 {
+        self.elapsed
+    }
+
+    /// The strength right now, faded out over the duration.
+    pub fn current_strength(&self) -> f32 {
+        match self.duration {
+            Some(duration) if duration > 0.0 => self.strength * (1.0 - self.elapsed / duration).max(0.0),
+            Some(_) => 0.0,
+            None => self.strength,
+        }
+    }
+
+    /// Radius of the expanding ring right now, `None` if the impulse pushes the whole circle.
+    pub fn front(&self) -> Option<f32> {
+        (self.expansion_speed > 0.0).then(|| (self.elapsed * self.expansion_speed).min(self.radius))
+    }
+
+    /// How far the impulse pushes a blade tip at `position` right now, outward from the entity's translation.
+    pub fn push(&self, transform: &GlobalTransform, position: Vec3) -> Vec2 {
+        let offset = position.xz() - transform.translation().xz();
+        let distance = offset.length();
+        if distance >= self.radius || distance < 0.0001 {
+            return Vec2::ZERO;
+        }
+
+        let falloff = self.falloff.max(0.0001);
+        let mut weight = 1.0 - smoothstep(self.radius - falloff, self.radius, distance);
+        if let Some(front) = self.front() {
+            weight *= 1.0 - smoothstep(0.0, falloff, (distance - front).abs());
+        }
+        offset / distance * self.current_strength() * weight
+    }
+}
+
+/// Despawns the entity once its [`WindImpulse`] finishes, so one-off impulses don't leave empty entities behind.
+/// Without it only the [`WindImpulse`] is removed, leaving the entity it was added to alone.
+#[derive(Component, Clone, Copy, Default)]
+pub struct DespawnFinishedImpulse;
+
+/// A standalone impulse, despawned when it finishes.
+#[derive(Bundle, Default)]
+pub struct WindImpulseBundle {
+    pub wind_impulse: WindImpulse,
+    pub despawn: DespawnFinishedImpulse,
+    #[bundle()]
+    pub transform: TransformBundle,
+}
+
+/// Ages the impulses and removes the finished ones.
+pub(crate) fn update_wind_impulses(
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut WindImpulse, Has<DespawnFinishedImpulse>)>,
+    time: Res<Time>,
+) {
+    for (entity, mut impulse, despawn) in &mut query {
+        impulse.elapsed += time.delta_seconds();
+        if impulse.duration.is_some_and(|duration| impulse.elapsed >= duration) {
+            if despawn {
+                commands.entity(entity).despawn_recursive();
+            } else {
+                commands.entity(entity).remove::<WindImpulse>();
+            }
+        }
+    }
+}
+
+/// Everything that sways the blades, for culling by how far they can reach.
+#[derive(SystemParam)]
+pub(crate) struct WindReach<'w, 's> {
+    global_wind: Res<'w, GrassWind>,
+    zones: Query<'w, 's, &'static WindZone>,
+    gusts: Res<'w, WindGusts>,
+    impulses: Query<'w, 's, &'static WindImpulse>,
+}
+
+impl WindReach<'_, '_> {
+    /// The wind of a field with the strongest sway of any of the zones.
+    pub fn strongest_wind(&self, local_wind: Option<&GrassWind>) -> Wind {
+        let wind = local_wind.unwrap_or(&self.global_wind).wind_data;
+        self.zones.iter().take(MAX_WIND_ZONES).fold(wind, |strongest, zone| Wind {
+            amplitude: strongest.amplitude.abs().max(zone.wind.amplitude.abs()),
+            oscillation: strongest.oscillation.abs().max(zone.wind.oscillation.abs()),
+            ..strongest
+        })
+    }
+
+    /// How much further the gusts and impulses can push the blade tips on top of the wind.
+    pub fn push(&self) -> f32 {
+        self.gusts.strength.abs()
+            + self.impulses.iter().take(MAX_WIND_IMPULSES).map(|impulse| impulse.strength.abs()).sum::<f32>()
+    }
+}
+
+/// Samples the global [`GrassWind`] with the [`WindZone`]s, [`WindGusts`] and [`WindImpulse`]s of the world at the
+/// current time, see [`GrassWind::sample`].
+#[derive(SystemParam)]
+pub struct GrassWindSampler<'w, 's> {
+    wind: Res<'w, GrassWind>,
+    images: Res<'w, Assets<Image>>,
+    time: Res<'w, Time>,
+    zones: Query<'w, 's, (&'static WindZone, &'static GlobalTransform)>,
+    gusts: Res<'w, WindGusts>,
+    impulses: Query<'w, 's, (&'static WindImpulse, &'static GlobalTransform)>,
+}
+
+impl GrassWindSampler<'_, '_> {
+    /// How far the global wind pushes a blade tip at `point` right now, zero until the wind map is loaded.
+    pub fn sample(&self, point: impl Into<WindSamplePoint>) -> Vec2 {
+        self.sample_local(&self.wind, point)
+    }
+
+    /// Like [`GrassWindSampler::sample`], for grass with its own [`GrassWind`].
+    pub fn sample_local(&self, wind: &GrassWind, point: impl Into<WindSamplePoint>) -> Vec2 {
+        let Some(wind_map) = self.images.get(&wind.wind_map) else {
+            return Vec2::ZERO;
+        };
+        wind.sample(wind_map, point, self.time.elapsed_seconds_wrapped(), &self.zones, &self.gusts, &self.impulses)
+    }
+}
+
+/// How the wind map of the global [`GrassWind`] is made at startup.
+#[derive(Resource, Clone)]
+pub struct WindMapSettings {
+    /// Width and height in pixels, each pixel takes 4 bytes.
+    pub size: u32,
+    /// How many noise features span the map, larger values give smaller, busier gusts.
+    pub scale: f64,
+    pub seed: u32,
+    pub noise: WindNoise,
+    /// Path of a wind map to load instead of generating one. Like a generated map, it has to tile and hold a 24 bit
+    /// noise value in its RGB channels, with red as the most significant byte.
+    pub path: Option<String>,
+}
+
+impl Default for WindMapSettings {
+    fn default() -> Self {
+        Self {
+            size: 2048,
+            scale: 4.0,
+            seed: 0,
+            noise: WindNoise::default(),
+            path: None,
+        }
+    }
+}
+
+/// The noise the wind map is generated from.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub enum WindNoise {
+    #[default]
+    PerlinSurflet,
+    Perlin,
+    Simplex,
+    /// Cellular noise, for patchy gusts.
+    Worley,
+    /// Layered Perlin noise, each octave adding finer detail.
+    Fbm { octaves: usize },
+}
+
+use noise::{NoiseFn, MultiFractal};
 use std::f64::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Where [`GrassWind::sample`] samples the wind. A bare position sways like the scatter instances, a blade's seed
+/// and base normal add the per-blade oscillation of the grass shader.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct WindSamplePoint {
+    pub position: Vec3,
+    /// The seed of the blade, its 16 bit seed over 65535.
+    pub seed: f32,
+    /// Horizontal normal of the blade's flat side, the blade oscillates along it.
+    pub base_normal: Vec2,
+}
+
+impl From<Vec3> for WindSamplePoint {
+    fn from(position: Vec3) -> Self {
+        Self {
+            position,
+            ..default()
+        }
+    }
+}
+
+impl Wind {
+    /// How far this wind pushes a blade tip, `wind_sway` of `wind.wgsl`. Wind maps that aren't 8 bit RGBA don't sway.
+    pub fn sway(&self, wind_map: &Image, point: WindSamplePoint, time: f32) -> Vec2 {
+        let rad = self.direction * std::f32::consts::PI / 180.0;
+        let wind_direction = Vec2::new(rad.cos(), rad.sin());
+
+        let position = point.position;
+        let random_point = fract(Vec2::new(position.x, position.y) * 0.1 * point.seed);
+        let wind_pos = fract(position.xz() / self.scale);
+        let (Some(random), Some(texel)) = (
+            load_wind_map(wind_map, random_point, self, time),
+            load_wind_map(wind_map, wind_pos, self, time),
+        ) else {
+            return Vec2::ZERO;
+        };
+        let t = unpack_float(texel);
+
+        -wind_direction * (0.5 * (t * self.frequency).sin()) * self.amplitude
+            + point.base_normal * (random.x * 0.2).sin() * self.oscillation
+    }
+}
 
 impl GrassWind {
-    pub fn generate_wind_map(size: usize, scale: f64) -> Image {
-        let perlin = noise::PerlinSurflet::new(0);
-    
-        let mut data = Vec::with_capacity(size * size * 4);
-
-        let (x1, y1, x2, y2) = (-1.0, -1.0, 1.0, 1.0);
-        for y in 0..size {
-            for x in 0..size {
-                let s = x as f64 / size as f64;
-                let t = y as f64 / size as f64;
-                let dx = x2 - x1;
-                let dy = y2 - y1;
-
-                let nx = x1 + (s * 2.0 * PI).cos() * (dx / (2.0 * PI));
-                let ny = y1 + (t * 2.0 * PI).cos() * (dy / (2.0 * PI));
-                let nz = x1 + (s * 2.0 * PI).sin() * (dx / (2.0 * PI));
-                let nw = y1 + (t * 2.0 * PI).sin() * (dy / (2.0 * PI));
-
-                let noise = perlin.get([nx * scale, ny * scale, nz * scale, nw * scale]);
-                let noise_scaled = ((noise + 1.0) / 2.0 * 16777215.0) as u32;
-
-                let r = ((noise_scaled >> 16) & 255) as u8;
-                let g = ((noise_scaled >> 8) & 255) as u8;
-                let b = (noise_scaled & 255) as u8;
-
-                data.push(r); 
-                data.push(g); 
-                data.push(b); 
-                data.push(255);
+    /// How far the wind pushes a blade tip at `point`, evaluated like the grass and scatter shaders do, so particles,
+    /// cloth or trees can sway in sync with the grass. `wind_map` is the loaded [`GrassWind::wind_map`] and `time` is
+    /// [`Time::elapsed_seconds_wrapped`], the time the shaders see. The [`WindZone`]s blend their sway in, then the
+    /// [`WindGusts::sway`] and [`WindImpulse::push`] are added on top. Like the shaders, only the first
+    /// [`MAX_WIND_ZONES`] zones and [`MAX_WIND_IMPULSES`] impulses count.
+    ///
+    /// Inside a system, [`GrassWindSampler`] gathers all of these.
+    pub fn sample<'a>(
+        &self,
+        wind_map: &Image,
+        point: impl Into<WindSamplePoint>,
+        time: f32,
+        zones: impl IntoIterator<Item = (&'a WindZone, &'a GlobalTransform)>,
+        gusts: &WindGusts,
+        impulses: impl IntoIterator<Item = (&'a WindImpulse, &'a GlobalTransform)>,
+    ) -> Vec2 {
+        let point = point.into();
+        let mut sway = self.wind_data.sway(wind_map, point, time);
+        for (zone, transform) in zones.into_iter().take(MAX_WIND_ZONES) {
+            let weight = zone.weight(transform, point.position);
+            if weight > 0.0 {
+                sway = sway.lerp(zone.wind.sway(wind_map, point, time), weight);
+            }
+        }
+
+        sway + gusts.sway(point.position)
+            + impulses.into_iter().take(MAX_WIND_IMPULSES).map(|(impulse, transform)| impulse.push(transform, point.position)).sum::<Vec2>()
+    }
+
+    /// Generates a tiling wind map, the same settings always give the same map.
+    pub fn generate_wind_map(settings: &WindMapSettings) -> Image {
+        let size = settings.size as usize;
+        let (seed, scale) = (settings.seed, settings.scale);
+        match settings.noise {
+            WindNoise::PerlinSurflet => generate_noise_map(&noise::PerlinSurflet::new(seed), size, scale),
+            WindNoise::Perlin => generate_noise_map(&noise::Perlin::new(seed), size, scale),
+            WindNoise::Simplex => generate_noise_map(&noise::Simplex::new(seed), size, scale),
+            WindNoise::Worley => generate_noise_map(&noise::Worley::new(seed), size, scale),
+            WindNoise::Fbm { octaves } => {
+                generate_noise_map(&noise::Fbm::<noise::Perlin>::new(seed).set_octaves(octaves), size, scale)
             }
         }
-    
-        Image::new(
-            Extent3d {
-                width: size as u32, 
-                height: size as u32, 
-                depth_or_array_layers: 1
-            },
-            TextureDimension::D2,
-            data,
-            TextureFormat::Rgba8UnormSrgb,
-        )
     }
 }
 
+/// Packs 4D noise sampled around two circles into the RGB channels, so the map tiles in both directions.
+fn generate_noise_map(noise: &impl NoiseFn<f64, 4>, size: usize, scale: f64) -> Image {
+    let mut data = Vec::with_capacity(size * size * 4);
+
+    let (x1, y1, x2, y2) = (-1.0, -1.0, 1.0, 1.0);
+    for y in 0..size {
+        for x in 0..size {
+            let s = x as f64 / size as f64;
+            let t = y as f64 / size as f64;
+            let dx = x2 - x1;
+            let dy = y2 - y1;
+
+            let nx = x1 + (s * 2.0 * PI).cos() * (dx / (2.0 * PI));
+            let ny = y1 + (t * 2.0 * PI).cos() * (dy / (2.0 * PI));
+            let nz = x1 + (s * 2.0 * PI).sin() * (dx / (2.0 * PI));
+            let nw = y1 + (t * 2.0 * PI).sin() * (dy / (2.0 * PI));
+
+            let value = noise.get([nx * scale, ny * scale, nz * scale, nw * scale]);
+            // fractal noise can overshoot -1..1
+            let noise_scaled = ((value + 1.0) / 2.0).clamp(0.0, 1.0) * 16777215.0;
+            let noise_scaled = noise_scaled as u32;
+
+            let r = ((noise_scaled >> 16) & 255) as u8;
+            let g = ((noise_scaled >> 8) & 255) as u8;
+            let b = (noise_scaled & 255) as u8;
+
+            data.push(r); 
+            data.push(g); 
+            data.push(b); 
+            data.push(255);
+        }
+    }
+
+    Image::new(
+        Extent3d {
+            width: size as u32, 
+            height: size as u32, 
+            depth_or_array_layers: 1
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8UnormSrgb,
+    )
+}
+
+/// The texel `sample_wind_map` loads in `wind.wgsl`, decoded the way the GPU decodes it.
+/// Empty wind maps and formats other than 8 bit RGBA return `None`.
+fn load_wind_map(wind_map: &Image, uv: Vec2, wind: &Wind, time: f32) -> Option<Vec3> {
+    let format = wind_map.texture_descriptor.format;
+    let size = wind_map.size();
+    if !matches!(format, TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm) || size.cmpeq(UVec2::ZERO).any() {
+        return None;
+    }
+
+    let rad = wind.direction * std::f32::consts::PI / 180.0;
+    let direction = Vec2::new(rad.cos(), rad.sin());
+
+    let scrolled_uv = uv + direction * time * wind.speed;
+
+    let pixel = (fract(scrolled_uv) * size.as_vec2()).as_uvec2().min(size - 1);
+    let index = (pixel.y * size.x + pixel.x) as usize * 4;
+    let texel = wind_map.data.get(index..index + 3)?;
+    let texel = Vec3::new(texel[0] as f32, texel[1] as f32, texel[2] as f32) / 255.0;
+
+    // loading from an sRGB texture converts the texel to linear
+    Some(if format.is_srgb() {
+        Vec3::new(texel.x.nonlinear_to_linear_srgb(), texel.y.nonlinear_to_linear_srgb(), texel.z.nonlinear_to_linear_srgb())
+    } else {
+        texel
+    })
+}
+
+/// `fract` of WGSL, which is always positive unlike [`f32::fract`].
+fn fract(v: Vec2) -> Vec2 {
+    v - v.floor()
+}
+
+fn unpack_float(rgb: Vec3) -> f32 {
+    let r = rgb.x * 255.0;
+    let g = rgb.y * 255.0;
+    let b = rgb.z * 255.0;
+
+    let noise_scaled = r * 65536.0 + g * 256.0 + b;
+    noise_scaled / 16777215.0
+}
+
+/// A wind map being generated on the [`AsyncComputeTaskPool`]. The task hands the map over through a shared slot,
+/// the pool only returns a pollable `Task` with Bevy's `multi_threaded` feature.
+#[derive(Component)]
+pub(crate) struct WindMapTask {
+    handle: Handle<Image>,
+    image: Arc<Mutex<Option<Image>>>,
+}
+
+/// Starts generating the wind map of the global [`GrassWind`] in the background, or loads it from
+/// [`WindMapSettings::path`]. The grass sways with the fallback image until the map is ready.
 pub fn create_wind_map(
+    mut commands: Commands,
     mut wind: ResMut<GrassWind>,
+    settings: Res<WindMapSettings>,
     asset_server: Res<AssetServer>,
+    images: Res<Assets<Image>>,
 ) {
-    wind.wind_map = asset_server.add(GrassWind::generate_wind_map(2048, 4.));
-}
\ No newline at end of file
+    if let Some(path) = &settings.path {
+        wind.wind_map = asset_server.load(path.clone());
+        return;
+    }
+
+    let handle = images.get_handle_provider().reserve_handle().typed::<Image>();
+    wind.wind_map = handle.clone();
+
+    let image = Arc::new(Mutex::new(None));
+    let slot = image.clone();
+    let settings = settings.clone();
+    AsyncComputeTaskPool::get()
+        .spawn(async move {
+            let image = GrassWind::generate_wind_map(&settings);
+            *slot.lock().unwrap() = Some(image);
+        })
+        .detach();
+    commands.spawn(WindMapTask { handle, image });
+}
+
+pub(crate) fn finish_wind_map(
+    mut commands: Commands,
+    tasks: Query<(Entity, &WindMapTask)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (entity, wind_map) in &tasks {
+        let Some(image) = wind_map.image.lock().unwrap().take() else {
+            continue;
+        };
+
+        images.insert(wind_map.handle.clone(), image);
+        commands.entity(entity).despawn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x4 wind map, black except for the texel at (1, 2).
+    fn wind_map(format: TextureFormat) -> Image {
+        let mut data = vec![0; 4 * 4 * 4];
+        let index = (2 * 4 + 1) * 4;
+        data[index..index + 4].copy_from_slice(&[0x80, 0x40, 0x20, 0xff]);
+        Image::new(Extent3d { width: 4, height: 4, depth_or_array_layers: 1 }, TextureDimension::D2, data, format)
+    }
+
+    fn grass_wind(speed: f32) -> GrassWind {
+        GrassWind {
+            wind_data: Wind {
+                speed,
+                amplitude: 2.0,
+                frequency: 3.0,
+                direction: 0.0,
+                scale: 4.0,
+                ..default()
+            },
+            wind_map: Handle::default(),
+        }
+    }
+
+    /// Samples only the wind map, without zones, gusts or impulses.
+    fn sample(wind: &GrassWind, map: &Image, position: Vec3, time: f32) -> Vec2 {
+        wind.sample(map, position, time, [], &WindGusts::default(), [])
+    }
+
+    #[test]
+    fn sample_matches_the_shader() {
+        let wind = grass_wind(0.0);
+        let sway = sample(&wind, &wind_map(TextureFormat::Rgba8Unorm), Vec3::new(1.5, 7.0, 2.5), 0.0);
+        // t = (128 * 65536 + 64 * 256 + 32) / 16777215 = 0.500978, sway = -0.5 * sin(3 * t) * 2 along x
+        let expected = Vec2::new(-0.997698, 0.0);
+
+        assert!(sway.abs_diff_eq(expected, 1e-5), "{sway} != {expected}");
+        assert_eq!(sample(&wind, &wind_map(TextureFormat::Rgba8Unorm), Vec3::new(0.5, 0.0, 0.5), 0.0), Vec2::ZERO);
+    }
+
+    #[test]
+    fn sample_decodes_srgb_wind_maps() {
+        let wind = grass_wind(0.0);
+        let sway = sample(&wind, &wind_map(TextureFormat::Rgba8UnormSrgb), Vec3::new(1.5, 0.0, 2.5), 0.0);
+        // the texel decodes to (55.044, 13.074, 3.683) / 255 so t = 0.215217, sway = -0.5 * sin(3 * t) * 2 along x
+        let expected = Vec2::new(-0.601719, 0.0);
+
+        assert!(sway.abs_diff_eq(expected, 1e-5), "{sway} != {expected}");
+    }
+
+    #[test]
+    fn sample_tiles_every_wind_scale() {
+        let wind = grass_wind(0.0);
+        let map = wind_map(TextureFormat::Rgba8Unorm);
+
+        // the uv wraps like WGSL's fract, also below zero
+        let sway = sample(&wind, &map, Vec3::new(1.5, 0.0, 2.5), 0.0);
+        assert_eq!(sample(&wind, &map, Vec3::new(-2.5, 0.0, 2.5), 0.0), sway);
+        assert_eq!(sample(&wind, &map, Vec3::new(9.5, 0.0, -5.5), 0.0), sway);
+    }
+
+    #[test]
+    fn sample_scrolls_with_time() {
+        let wind = grass_wind(0.25);
+        let map = wind_map(TextureFormat::Rgba8Unorm);
+
+        // a quarter of the map, one texel, per second
+        let sway = sample(&wind, &map, Vec3::new(1.5, 0.0, 2.5), 0.0);
+        assert_ne!(sway, Vec2::ZERO);
+        assert_eq!(sample(&wind, &map, Vec3::new(0.5, 0.0, 2.5), 1.0), sway);
+        assert_eq!(sample(&wind, &map, Vec3::new(3.5, 0.0, 2.5), 2.0), sway);
+    }
+
+    #[test]
+    fn sample_skips_unsupported_wind_maps() {
+        let wind = grass_wind(0.0);
+        let map = wind_map(TextureFormat::R32Float);
+        assert_eq!(sample(&wind, &map, Vec3::new(1.5, 0.0, 2.5), 0.0), Vec2::ZERO);
+
+        let empty = Image::new(Extent3d { width: 0, height: 0, depth_or_array_layers: 1 }, TextureDimension::D2, Vec::new(), TextureFormat::Rgba8Unorm);
+        assert_eq!(sample(&wind, &empty, Vec3::new(1.5, 0.0, 2.5), 0.0), Vec2::ZERO);
+    }
+
+    #[test]
+    fn sample_blends_zones_and_adds_gusts_and_impulses() {
+        let wind = grass_wind(0.0);
+        let map = wind_map(TextureFormat::Rgba8Unorm);
+        // the seed picks the texel at (1, 2) for the oscillation too
+        let point = WindSamplePoint {
+            position: Vec3::new(5.5, 12.0, 2.5),
+            seed: 0.5,
+            base_normal: Vec2::Y,
+        };
+
+        // 4 from the center so 2 outside the sphere, halfway through the falloff
+        let zone = WindZone {
+            shape: WindZoneShape::Sphere { radius: 2.0 },
+            falloff: 4.0,
+            blend: 1.0,
+            wind: Wind {
+                speed: 0.0,
+                amplitude: 1.0,
+                frequency: 3.0,
+                direction: 90.0,
+                oscillation: 0.5,
+                scale: 4.0,
+                ..default()
+            },
+        };
+        let zone_transform = GlobalTransform::from_translation(Vec3::new(5.5, 12.0, 6.5));
+
+        // fronts 12 apart travelling along -x, 0.5 from the nearest one
+        let gusts = WindGusts {
+            frequency: 0.5,
+            strength: 1.5,
+            width: 4.0,
+            speed: 6.0,
+            state: GustState { elapsed: 0.0, direction: 0.0, offset: 6.0 },
+            ..default()
+        };
+
+        // a quarter through, at 1.5 strength with the ring at 4, 5 away from the blade
+        let impulse = WindImpulse {
+            radius: 8.0,
+            strength: 2.0,
+            duration: Some(2.0),
+            falloff: 4.0,
+            expansion_speed: 8.0,
+            elapsed: 0.5,
+        };
+        let impulse_transform = GlobalTransform::from_translation(Vec3::new(2.5, 0.0, 6.5));
+
+        // r = 128 / 255, t = 0.500978
+        // wind: -x * 0.5 * sin(3 * t) * 2 + y * sin(0.2 * r) * 1.5 = (-0.997698, 0.150335)
+        // zone: -y * 0.5 * sin(3 * t) + y * sin(0.2 * r) * 0.5 = (0, -0.448737), mixed halfway
+        // gust: -x * 1.5 * (1 - smoothstep(0, 2, 0.5)) = (-1.265625, 0)
+        // impulse: (0.6, -0.8) * 1.5 * (1 - smoothstep(4, 8, 5)) * (1 - smoothstep(0, 4, 1)) = (0.640723, -0.854297)
+        let expected = Vec2::new(-1.123752, -1.003498);
+        let sway = wind.sample(&map, point, 0.0, [(&zone, &zone_transform)], &gusts, [(&impulse, &impulse_transform)]);
+        assert!(sway.abs_diff_eq(expected, 1e-5), "{sway} != {expected}");
+
+        // the parts sum up, and a bare position doesn't oscillate
+        assert!((zone.weight(&zone_transform, point.position) - 0.5).abs() < 1e-6);
+        assert!(gusts.sway(point.position).abs_diff_eq(Vec2::new(-1.265625, 0.0), 1e-5));
+        assert!(impulse.push(&impulse_transform, point.position).abs_diff_eq(Vec2::new(0.640723, -0.854297), 1e-5));
+        assert!(wind.wind_data.sway(&map, point.position.into(), 0.0).abs_diff_eq(Vec2::new(-0.997698, 0.0), 1e-5));
+    }
+}
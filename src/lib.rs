@@ -1,7 +1,7 @@
-use bevy::{prelude::*, render::{render_asset::RenderAssetPlugin, extract_component::ExtractComponentPlugin, RenderApp, render_resource::SpecializedMeshPipelines, Render, render_phase::AddRenderCommand, RenderSet, extract_resource::ExtractResourcePlugin}, core_pipeline::core_3d::Opaque3d, asset::load_internal_asset};
+use bevy::{prelude::*, core_pipeline::core_3d::{Opaque3d, AlphaMask3d, Transparent3d}, render::{ExtractSchedule, render_phase::AddRenderCommand, render_resource::SpecializedMeshPipelines, extract_component::ExtractComponentPlugin, RenderApp, Render, RenderSet, extract_resource::ExtractResourcePlugin, render_graph::{RenderGraphApp, ViewNodeRunner}}, core_pipeline::core_3d, asset::load_internal_asset};
 
-use grass::{chunk::GrassChunks, grass::{Grass, GrassLODMesh}, wind::GrassWind, config::GrassConfig};
-use render::{instance::GrassChunkData, pipeline::GrassPipeline, draw::DrawGrass};
+use grass::{chunk::GrassChunks, grass::{Grass, GrassLODMesh}, wind::{GrassWind, PreviousWind, WindGusts, WindMapSettings}, config::GrassConfig, material::{GrassMaterialPlugin, StandardGrassMaterial}, scatter::Scatter, season::GrassSeason, coverage::GrassCoverage};
+use render::{instance::{GrassChunkData, ExtractedGrassChunkData, RenderGrassChunkData}, pipeline::{GrassPipeline, GrassCullPipeline, ScatterPipeline, ScatterMeshUniform}, node::{GrassCullNode, GRASS_CULL_NODE}, prepare::{GrassBuffers, LocalWindBuffers, GrassCullViewBuffers, ScatterBuffers, SeasonBuffer, CoverageBuffer, WindFieldBuffer}, draw::DrawScatter};
 
 pub mod grass;
 mod render;
@@ -10,9 +10,13 @@ mod util;
 pub mod prelude {
     pub use crate::ProceduralGrassPlugin;
     pub use crate::grass::{
-        grass::{GrassBundle, Grass, GrassLODMesh}, 
+        grass::{GrassBundle, GrassMaterialBundle, Grass, GrassVariant, GrassLODMesh, GrassShading}, 
+        material::{GrassMaterial, GrassMaterialPlugin, StandardGrassMaterial, GrassAlphaMode},
         mesh::GrassMesh, 
-        wind::{GrassWind, Wind},
+        wind::{GrassWind, GrassWindSampler, WindSamplePoint, Wind, WindZoneBundle, WindZone, WindZoneShape, WindGusts, WindImpulseBundle, WindImpulse, DespawnFinishedImpulse, WindMapSettings, WindNoise},
+        scatter::{ScatterBundle, Scatter},
+        season::GrassSeason,
+        coverage::GrassCoverage,
         config::GrassConfig,
     };
 }
@@ -20,10 +24,23 @@ pub mod prelude {
 pub(crate) const GRASS_SHADER_HANDLE: Handle<Shader> =
     Handle::weak_from_u128(195_094_223_228_228_028_086_047_086_167_255_040_126);
 
+pub(crate) const GRASS_CULL_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(214_376_097_012_848_391_202_517_663_085_118_904_733);
+
+pub(crate) const SCATTER_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(301_665_820_490_135_743_058_216_374_901_268_539_117);
+
+pub(crate) const WIND_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(87_431_905_226_741_318_530_944_720_167_204_961_583);
+
 #[derive(Default, Clone)]
 pub struct ProceduralGrassPlugin {
     pub config: GrassConfig,
     pub wind: GrassWind,
+    pub wind_map: WindMapSettings,
+    pub gusts: WindGusts,
+    pub season: GrassSeason,
+    pub coverage: GrassCoverage,
 }
 
 impl Plugin for ProceduralGrassPlugin {
@@ -34,49 +51,111 @@ impl Plugin for ProceduralGrassPlugin {
             "assets/shaders/grass.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            GRASS_CULL_SHADER_HANDLE,
+            "assets/shaders/grass_cull.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            SCATTER_SHADER_HANDLE,
+            "assets/shaders/scatter.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            WIND_SHADER_HANDLE,
+            "assets/shaders/wind.wgsl",
+            Shader::from_wgsl
+        );
 
         #[cfg(feature = "bevy-inspector-egui")]
         {
             app 
                 .register_type::<Grass>()
                 .register_type::<GrassWind>()
-                .register_type::<GrassConfig>();
+                .register_type::<grass::wind::WindZone>()
+                .register_type::<grass::wind::WindImpulse>()
+                .register_type::<WindGusts>()
+                .register_type::<GrassConfig>()
+                .register_type::<GrassSeason>()
+                .register_type::<GrassCoverage>();
         }
         app
             .insert_resource(self.wind.clone())
+            .insert_resource(self.wind_map.clone())
+            .insert_resource(self.gusts.clone())
             .insert_resource(self.config)
+            .insert_resource(self.season.clone())
+            .insert_resource(self.coverage.clone())
+            .init_resource::<PreviousWind>()
             .add_systems(Startup, grass::wind::create_wind_map)
-            .add_systems(PostStartup, grass::grass::generate_grass)
-            .add_systems(Update, grass::chunk::grass_culling)
+            .add_systems(Update, grass::wind::finish_wind_map)
+            .add_systems(PreUpdate, (grass::wind::update_previous_wind, grass::wind::update_wind_gusts, grass::wind::update_wind_impulses))
+            .add_systems(Update, (grass::grass::generate_grass, grass::chunk::grass_culling).chain())
+            .add_systems(Update, (grass::scatter::generate_scatter, grass::scatter::scatter_culling).chain())
             .init_asset::<GrassChunkData>()
-            .add_plugins(RenderAssetPlugin::<GrassChunkData>::default())
             .add_plugins((
                 ExtractComponentPlugin::<Grass>::default(),
                 ExtractComponentPlugin::<GrassChunks>::default(),
                 ExtractComponentPlugin::<GrassLODMesh>::default(),
+                ExtractComponentPlugin::<Scatter>::default(),
                 ExtractComponentPlugin::<GrassWind>::default(),
                 ExtractResourcePlugin::<GrassWind>::default(),
+                ExtractResourcePlugin::<PreviousWind>::default(),
+                ExtractResourcePlugin::<GrassConfig>::default(),
+                ExtractResourcePlugin::<GrassSeason>::default(),
+                ExtractResourcePlugin::<GrassCoverage>::default(),
             ));
 
+        app.add_plugins(GrassMaterialPlugin::<StandardGrassMaterial>::default());
+        app.world.resource_mut::<Assets<StandardGrassMaterial>>().insert(Handle::<StandardGrassMaterial>::default(), StandardGrassMaterial::default());
+
         let render_app = app.sub_app_mut(RenderApp);
-        render_app.add_render_command::<Opaque3d, DrawGrass>()
-        .init_resource::<SpecializedMeshPipelines<GrassPipeline>>()
+        render_app.init_resource::<GrassBuffers>()
+        .init_resource::<LocalWindBuffers>()
+        .init_resource::<GrassCullViewBuffers>()
+        .init_resource::<ScatterBuffers>()
+        .init_resource::<SeasonBuffer>()
+        .init_resource::<CoverageBuffer>()
+        .init_resource::<WindFieldBuffer>()
+        .init_resource::<ExtractedGrassChunkData>()
+        .init_resource::<RenderGrassChunkData>()
+        .add_systems(ExtractSchedule, (render::prepare::extract_wind_field, render::instance::extract_grass_chunk_data))
+        .init_resource::<SpecializedMeshPipelines<ScatterPipeline>>()
+        .add_render_command::<Opaque3d, DrawScatter>()
+        .add_render_command::<AlphaMask3d, DrawScatter>()
+        .add_render_command::<Transparent3d, DrawScatter>()
         .add_systems(
             Render,
             (
-                render::queue::grass_queue.in_set(RenderSet::QueueMeshes),
+                render::instance::prepare_grass_chunk_data.in_set(RenderSet::PrepareAssets),
                 render::prepare::prepare_grass_buffers.in_set(RenderSet::PrepareResources),
+                render::prepare::prepare_season_buffer.in_set(RenderSet::PrepareResources),
+                render::prepare::prepare_coverage_buffer.in_set(RenderSet::PrepareResources),
+                render::prepare::prepare_wind_field_buffer.in_set(RenderSet::PrepareResources),
                 render::prepare::prepare_global_wind_buffers.in_set(RenderSet::PrepareResources),
                 render::prepare::prepare_local_wind_buffers.in_set(RenderSet::PrepareResources),
                 render::prepare::prepare_grass_bind_group.in_set(RenderSet::PrepareBindGroups),
                 render::prepare::prepare_global_wind_bind_group.in_set(RenderSet::PrepareBindGroups),
                 render::prepare::prepare_local_wind_bind_group.in_set(RenderSet::PrepareBindGroups),
+                render::prepare::prepare_indirect_buffers.in_set(RenderSet::PrepareResources),
+                render::prepare::prepare_cull_view_bind_group.in_set(RenderSet::PrepareBindGroups),
+                render::prepare::prepare_scatter_buffers.in_set(RenderSet::PrepareResources),
+                render::prepare::prepare_scatter_bind_group.in_set(RenderSet::PrepareBindGroups),
+                render::queue::scatter_queue.in_set(RenderSet::QueueMeshes),
             ),
-        );
+        )
+        .add_render_graph_node::<ViewNodeRunner<GrassCullNode>>(core_3d::graph::NAME, GRASS_CULL_NODE)
+        .add_render_graph_edges(core_3d::graph::NAME, &[GRASS_CULL_NODE, core_3d::graph::node::PREPASS]);
     }
 
     fn finish(&self, app: &mut App) {
         app.sub_app_mut(RenderApp)
-            .init_resource::<GrassPipeline>();
+            .init_resource::<GrassPipeline>()
+            .init_resource::<GrassCullPipeline>()
+            .init_resource::<ScatterPipeline>()
+            .init_resource::<ScatterMeshUniform>();
     }
 }
\ No newline at end of file
@@ -1,18 +1,94 @@
-use bevy::{prelude::*, render::{render_phase::{SetItemPipeline, PhaseItem, RenderCommand, TrackedRenderPass, RenderCommandResult}, render_asset::RenderAssets, mesh::GpuBufferInfo}, pbr::{SetMeshViewBindGroup, SetMeshBindGroup, RenderMeshInstances}, ecs::system::{lifetimeless::{SRes, Read}, SystemParamItem}};
+use std::{marker::PhantomData, ops::Range};
 
-use crate::grass::{wind::GrassWind, chunk::{RenderGrassChunks, GrassLOD}, grass::{Grass, GrassLODMesh}};
+use bevy::{prelude::*, render::{render_phase::{SetItemPipeline, PhaseItem, RenderCommand, TrackedRenderPass, RenderCommandResult}, render_asset::RenderAssets, mesh::{GpuBufferInfo, GpuMesh}}, pbr::{SetMeshViewBindGroup, RenderMeshInstances, SetPrepassViewBindGroup, RenderMaterials}, ecs::system::{lifetimeless::{SRes, Read}, SystemParamItem}};
 
-use super::{prepare::BufferBindGroup, instance::GrassChunkData};
+use crate::grass::{wind::GrassWind, chunk::{RenderGrassChunks, GrassLOD}, grass::{Grass, GrassLODMesh, RenderGrassVariants}, config::GrassConfig, material::GrassMaterial, scatter::Scatter};
 
-pub type DrawGrass = (
+use super::{prepare::BufferBindGroup, instance::{RenderGrassChunkData, GrassData}, material::RenderGrassMaterials, pipeline::ScatterMeshUniform};
+
+/// Size of the indexed indirect draw arguments, one set per LOD and variant in the indirect buffer.
+pub(crate) const INDIRECT_ARGS_SIZE: usize = 5 * std::mem::size_of::<u32>();
+
+pub type DrawGrass<M> = (
     SetItemPipeline,
     SetMeshViewBindGroup<0>,
-    SetMeshBindGroup<1>,
+    SetGrassMaterialBindGroup<M, 1>,
+    SetGrassBindGroup<2>,
+    SetWindBindGroup<3>,
+    DrawGrassInstanced<false>,
+);
+
+pub type DrawGrassPrepass<M> = (
+    SetItemPipeline,
+    SetPrepassViewBindGroup<0>,
+    SetGrassMaterialBindGroup<M, 1>,
+    SetGrassBindGroup<2>,
+    SetWindBindGroup<3>,
+    DrawGrassInstanced<false>,
+);
+
+/// Draws every blade of every chunk, the culled instances and chunk ranges only hold what the camera can see while the
+/// lights can cast shadows from grass outside of its frustum.
+pub type DrawGrassShadow<M> = (
+    SetItemPipeline,
+    SetPrepassViewBindGroup<0>,
+    SetGrassMaterialBindGroup<M, 1>,
     SetGrassBindGroup<2>,
     SetWindBindGroup<3>,
-    DrawGrassInstanced,
+    DrawGrassInstanced<true>,
+);
+
+pub type DrawScatter = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetScatterMaterialBindGroup<1>,
+    SetScatterBindGroup<2>,
+    SetWindBindGroup<3>,
+    DrawScatterInstanced,
 );
 
+pub struct SetGrassMaterialBindGroup<M: GrassMaterial, const I: usize>(PhantomData<M>);
+impl<P: PhaseItem, M: GrassMaterial, const I: usize> RenderCommand<P> for SetGrassMaterialBindGroup<M, I> {
+    type Param = SRes<RenderGrassMaterials<M>>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<Handle<M>>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        material_handle: &'w Handle<M>,
+        materials: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(material) = materials.into_inner().get(&material_handle.id()) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, &material.bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+pub struct SetScatterMaterialBindGroup<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetScatterMaterialBindGroup<I> {
+    type Param = SRes<RenderMaterials<StandardMaterial>>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<Scatter>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        scatter: &'w Scatter,
+        materials: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(material) = materials.into_inner().get(&scatter.material.id()) else {
+            return RenderCommandResult::Failure;
+        };
+        pass.set_bind_group(I, &material.bind_group, &[]);
+        RenderCommandResult::Success
+    }
+}
+
 pub struct SetGrassBindGroup<const I: usize>;
 impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetGrassBindGroup<I> {
     type Param = ();
@@ -34,6 +110,30 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetGrassBindGroup<I> {
     }
 }
 
+pub struct SetScatterBindGroup<const I: usize>;
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetScatterBindGroup<I> {
+    type Param = SRes<ScatterMeshUniform>;
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Option<Read<BufferBindGroup<Scatter>>>;
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        bind_group: Option<&'w BufferBindGroup<Scatter>>,
+        mesh_uniform: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = bind_group else {
+            return RenderCommandResult::Failure;
+        };
+        match mesh_uniform.dynamic_offset {
+            Some(offset) => pass.set_bind_group(I, &bind_group.bind_group, &[offset]),
+            None => pass.set_bind_group(I, &bind_group.bind_group, &[]),
+        }
+        RenderCommandResult::Success
+    }
+}
+
 pub struct SetWindBindGroup<const I: usize>;
 impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetWindBindGroup<I> {
     type Param = SRes<BufferBindGroup<GrassWind>>;
@@ -57,18 +157,19 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetWindBindGroup<I> {
     }
 }
  
-pub struct DrawGrassInstanced;
-impl<P: PhaseItem> RenderCommand<P> for DrawGrassInstanced {
-    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderMeshInstances>, SRes<RenderAssets<GrassChunkData>>);
+/// Draws the instances culled against the camera, or every instance with the high LOD meshes when `ALL_CHUNKS` is set.
+pub struct DrawGrassInstanced<const ALL_CHUNKS: bool>;
+impl<P: PhaseItem, const ALL_CHUNKS: bool> RenderCommand<P> for DrawGrassInstanced<ALL_CHUNKS> {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderMeshInstances>, SRes<RenderGrassChunkData>, SRes<GrassConfig>);
     type ViewWorldQuery = ();
-    type ItemWorldQuery = (Read<GrassLODMesh>, Read<RenderGrassChunks>);
+    type ItemWorldQuery = (Read<GrassLODMesh>, Read<RenderGrassChunks>, Read<RenderGrassVariants>);
 
     #[inline]
     fn render<'w>( 
         item: &P,
         _view: (),
-        (lod, chunks): (&'w GrassLODMesh, &'w RenderGrassChunks),
-        (meshes, render_mesh_instances, grass_data): SystemParamItem<'w, '_, Self::Param>,
+        (lod, chunks, variants): (&'w GrassLODMesh, &'w RenderGrassChunks, &'w RenderGrassVariants),
+        (meshes, render_mesh_instances, grass_data, config): SystemParamItem<'w, '_, Self::Param>,
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
         let Some(mesh_instance) = render_mesh_instances.get(&item.entity()) else {
@@ -91,21 +192,26 @@ impl<P: PhaseItem> RenderCommand<P> for DrawGrassInstanced {
             gpu_mesh_high
         };
 
-        let grass_data_inner = grass_data.into_inner();
-
-        for (i, chunk) in chunks.0.iter().enumerate() {
-            let gpu_grass = match grass_data_inner.get(chunk.1.clone()) {
-                Some(gpu_grass) => gpu_grass,
-                None => return RenderCommandResult::Failure,
-            };
+        // variants with their own mesh use it at every LOD
+        let variant_mesh = |variant: usize, lod: GrassLOD| -> Option<&'w GpuMesh> {
+            match variants.meshes.get(variant).copied().flatten() {
+                Some(mesh) => meshes.get(mesh),
+                None => match lod {
+                    GrassLOD::High => Some(gpu_mesh_high),
+                    GrassLOD::Low => Some(gpu_mesh_low),
+                },
+            }
+        };
 
-            let gpu_mesh = match chunk.0 {
-                GrassLOD::Low => &gpu_mesh_low,
-                GrassLOD::High => &gpu_mesh_high,
-            };
+        let Some(gpu_grass) = grass_data.into_inner().get(&chunks.instances) else {
+            return RenderCommandResult::Failure;
+        };
+        if gpu_grass.length == 0 {
+            return RenderCommandResult::Success;
+        }
 
+        let draw_range = |pass: &mut TrackedRenderPass<'w>, gpu_mesh: &'w GpuMesh, range: Range<u32>| {
             pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
-            pass.set_vertex_buffer(1, gpu_grass.buffer.slice(..));
 
             match &gpu_mesh.buffer_info {
                 GpuBufferInfo::Indexed {
@@ -114,14 +220,126 @@ impl<P: PhaseItem> RenderCommand<P> for DrawGrassInstanced {
                     count,
                 } => {
                     pass.set_index_buffer(buffer.slice(..), 0, *index_format);
-                    pass.draw_indexed(0..*count, 0, 0..gpu_grass.length as u32);
+                    pass.draw_indexed(0..*count, 0, range);
                 }
                 GpuBufferInfo::NonIndexed => {
-                    pass.draw(0..gpu_mesh.vertex_count, 0..gpu_grass.length as u32);
+                    pass.draw(0..gpu_mesh.vertex_count, range);
+                }
+            }
+        };
+
+        if ALL_CHUNKS {
+            pass.set_vertex_buffer(1, gpu_grass.buffer.slice(..));
+
+            for (variant, range) in gpu_grass.variants.iter().enumerate() {
+                if range.is_empty() {
+                    continue;
                 }
+                let Some(gpu_mesh) = variant_mesh(variant, GrassLOD::High) else {
+                    return RenderCommandResult::Failure;
+                };
+                draw_range(pass, gpu_mesh, range.clone());
             }
+
+            return RenderCommandResult::Success;
+        }
+
+        if let (true, Some(cull)) = (config.gpu_culling, &gpu_grass.cull) {
+            let instance_size = std::mem::size_of::<GrassData>() as u64;
+            let lod_size = gpu_grass.length as u64 * instance_size;
+
+            for (i, lod) in [GrassLOD::High, GrassLOD::Low].into_iter().enumerate() {
+                for (variant, range) in gpu_grass.variants.iter().enumerate() {
+                    if range.is_empty() {
+                        continue;
+                    }
+                    let Some(gpu_mesh) = variant_mesh(variant, lod) else {
+                        return RenderCommandResult::Failure;
+                    };
+
+                    pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+                    pass.set_vertex_buffer(1, cull.instance_buffer.slice(i as u64 * lod_size + range.start as u64 * instance_size..));
+
+                    let indirect_offset = ((i * gpu_grass.variants.len() + variant) * INDIRECT_ARGS_SIZE) as u64;
+                    match &gpu_mesh.buffer_info {
+                        GpuBufferInfo::Indexed {
+                            buffer,
+                            index_format,
+                            ..
+                        } => {
+                            pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                            pass.draw_indexed_indirect(&cull.indirect_buffer, indirect_offset);
+                        }
+                        GpuBufferInfo::NonIndexed => {
+                            pass.draw_indirect(&cull.indirect_buffer, indirect_offset);
+                        }
+                    }
+                }
+            }
+
+            return RenderCommandResult::Success;
+        }
+
+        pass.set_vertex_buffer(1, gpu_grass.buffer.slice(..));
+
+        for (lod, variant, range) in chunks.ranges.iter() {
+            let Some(gpu_mesh) = variant_mesh(*variant, *lod) else {
+                return RenderCommandResult::Failure;
+            };
+            draw_range(pass, gpu_mesh, range.clone());
         }
         
         RenderCommandResult::Success
     }
-}
\ No newline at end of file
+}
+
+/// Draws the visible instances of a scatter with the mesh of the entity, at every LOD.
+pub struct DrawScatterInstanced;
+impl<P: PhaseItem> RenderCommand<P> for DrawScatterInstanced {
+    type Param = (SRes<RenderAssets<Mesh>>, SRes<RenderMeshInstances>, SRes<RenderGrassChunkData>);
+    type ViewWorldQuery = ();
+    type ItemWorldQuery = Read<RenderGrassChunks>;
+
+    #[inline]
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        chunks: &'w RenderGrassChunks,
+        (meshes, render_mesh_instances, grass_data): SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(mesh_instance) = render_mesh_instances.get(&item.entity()) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+            return RenderCommandResult::Failure;
+        };
+        let Some(gpu_scatter) = grass_data.into_inner().get(&chunks.instances) else {
+            return RenderCommandResult::Failure;
+        };
+        if gpu_scatter.length == 0 {
+            return RenderCommandResult::Success;
+        }
+
+        pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, gpu_scatter.buffer.slice(..));
+
+        for (_, _, range) in chunks.ranges.iter() {
+            match &gpu_mesh.buffer_info {
+                GpuBufferInfo::Indexed {
+                    buffer,
+                    index_format,
+                    count,
+                } => {
+                    pass.set_index_buffer(buffer.slice(..), 0, *index_format);
+                    pass.draw_indexed(0..*count, 0, range.clone());
+                }
+                GpuBufferInfo::NonIndexed => {
+                    pass.draw(0..gpu_mesh.vertex_count, range.clone());
+                }
+            }
+        }
+
+        RenderCommandResult::Success
+    }
+}
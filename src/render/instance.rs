@@ -1,53 +1,303 @@
-use bevy::{prelude::*, reflect::TypeUuid, render::{render_asset::{RenderAsset, PrepareAssetError}, render_resource::{Buffer, BufferInitDescriptor, BufferUsages}, renderer::RenderDevice}, ecs::system::{lifetimeless::SRes, SystemParamItem}};
+use bevy::{prelude::*, reflect::TypeUuid, render::{render_resource::{Buffer, BufferInitDescriptor, BufferUsages, BindGroup, BufferDescriptor, BindGroupEntries}, renderer::RenderDevice, MainWorld}, ecs::event::ManualEventReader, utils::{HashMap, HashSet}};
+use std::ops::Range;
+
 use bytemuck::{Pod, Zeroable};
 
+use crate::grass::{config::GrassConfig, chunk::GrassDataUsage};
+
+use super::pipeline::GrassCullPipeline;
+
+/// A single blade instance, quantized to 20 bytes.
 #[derive(Clone, Copy, Pod, Zeroable, Reflect, Debug)]
 #[repr(C)]
 pub struct GrassData {
-    pub position: Vec3,
-    pub normal: Vec3,
-    pub chunk_uvw: Vec3,
+    /// Position inside the chunk, 16 bits per axis over the chunk size.
+    pub position: [u16; 3],
+    /// Octahedral encoded normal, 8 bits per component.
+    pub normal: u16,
+    pub chunk: [i16; 3],
+    /// Color map tint as sRGB 5:6:5.
+    pub tint: u16,
+    /// Seed in the low 16 bits, then 8 bits of height scale and the blade type in the top 8 bits.
+    pub packed: u32,
+}
+
+impl GrassData {
+    pub fn new(chunk: (i32, i32, i32), chunk_uvw: Vec3, normal: Vec3, tint: Color, seed: u16, height_scale: f32, blade_type: u8) -> Self {
+        let position = (chunk_uvw.clamp(Vec3::ZERO, Vec3::ONE) * u16::MAX as f32).round();
+        let normal = (octahedral_encode(normal) * u8::MAX as f32).round();
+        let height_scale = (height_scale.clamp(0.0, 1.0) * u8::MAX as f32).round() as u32;
+        let [r, g, b, _] = tint.as_rgba_f32();
+        let tint = ((r * 31.0).round() as u16) << 11 | ((g * 63.0).round() as u16) << 5 | (b * 31.0).round() as u16;
+
+        Self {
+            position: [position.x as u16, position.y as u16, position.z as u16],
+            normal: normal.x as u16 | (normal.y as u16) << 8,
+            chunk: [chunk.0 as i16, chunk.1 as i16, chunk.2 as i16],
+            tint,
+            packed: seed as u32 | height_scale << 16 | (blade_type as u32) << 24,
+        }
+    }
+
+    /// Index of the [`GrassVariant`](crate::grass::grass::GrassVariant) of the blade.
+    pub fn blade_type(&self) -> u8 {
+        (self.packed >> 24) as u8
+    }
+
+    pub fn world_position(&self, chunk_size: f32) -> Vec3 {
+        let chunk = Vec3::new(self.chunk[0] as f32, self.chunk[1] as f32, self.chunk[2] as f32);
+        let position = Vec3::new(self.position[0] as f32, self.position[1] as f32, self.position[2] as f32) / u16::MAX as f32;
+        (chunk + position) * chunk_size
+    }
+}
+
+// matches `bevy_pbr::utils::octahedral_encode`, mapped to 0..1
+fn octahedral_encode(v: Vec3) -> Vec2 {
+    let n = v / (v.x.abs() + v.y.abs() + v.z.abs());
+    let n_xy = if n.z >= 0.0 {
+        n.xy()
+    } else {
+        (1.0 - n.yx().abs()) * Vec2::select(n.xy().cmpgt(Vec2::ZERO), Vec2::ONE, Vec2::NEG_ONE)
+    };
+    n_xy * 0.5 + 0.5
 }
 
 pub struct GrassChunkBuffer {
     pub buffer: Buffer,
     pub length: usize,
+    /// Instance range of each variant in the buffer.
+    pub variants: Vec<Range<u32>>,
+    pub cull: Option<GrassCullBuffer>,
 }
 
-#[derive(Component, Deref, Clone, Asset, TypeUuid, TypePath)]
-#[uuid = "81a29e63-ef6c-4561-b49c-4a138ff39c01"]
-pub struct GrassChunkData(pub Vec<GrassData>);
+/// Buffers written by the culling compute pass, only allocated while [`GrassConfig::gpu_culling`] is enabled.
+/// The culled instances are split in two, high LOD first then low LOD, each laid out by variant like the instance buffer.
+/// There is an indirect draw per LOD and variant, in the same order.
+pub struct GrassCullBuffer {
+    pub instance_buffer: Buffer,
+    pub indirect_buffer: Buffer,
+    pub bind_group: BindGroup,
+}
+
+impl GrassCullBuffer {
+    fn new(render_device: &RenderDevice, cull_pipeline: &GrassCullPipeline, buffer: &Buffer, variants: &[Range<u32>]) -> Self {
+        let instance_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("culled grass buffer"),
+            size: buffer.size() * 2,
+            usage: BufferUsages::VERTEX | BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let indirect_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("grass indirect buffer"),
+            contents: bytemuck::cast_slice(&vec![0u32; 10 * variants.len()]),
+            usage: BufferUsages::INDIRECT | BufferUsages::STORAGE | BufferUsages::COPY_DST
+        });
+
+        // where each variant starts in the LOD halves of the culled instances
+        let variant_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("grass variant buffer"),
+            contents: bytemuck::cast_slice(&variants.iter().map(|range| range.start).collect::<Vec<_>>()),
+            usage: BufferUsages::STORAGE
+        });
 
-impl Default for GrassChunkData {
-    fn default() -> Self {
-        Self(Vec::new())
+        let bind_group = render_device.create_bind_group(
+            Some("grass cull chunk bind group"),
+            &cull_pipeline.chunk_layout,
+            &BindGroupEntries::sequential((
+                buffer.as_entire_binding(),
+                instance_buffer.as_entire_binding(),
+                indirect_buffer.as_entire_binding(),
+                variant_buffer.as_entire_binding(),
+            )),
+        );
+
+        Self {
+            instance_buffer,
+            indirect_buffer,
+            bind_group,
+        }
     }
 }
 
-impl RenderAsset for GrassChunkData {
-    type ExtractedAsset = GrassChunkData;
-    type PreparedAsset = GrassChunkBuffer;
-    type Param = SRes<RenderDevice>;
+/// Every blade instance of a grass entity, grouped by variant then chunk.
+/// With [`GrassDataUsage::RenderWorld`] the instances are moved out to the render world the first time the asset is
+/// extracted, so the main world copy is empty afterwards and later changes to it are ignored.
+#[derive(Asset, TypeUuid, TypePath, Clone)]
+#[uuid = "81a29e63-ef6c-4561-b49c-4a138ff39c01"]
+pub struct GrassChunkData {
+    pub instances: Vec<GrassData>,
+    /// Instance range of each variant in [`GrassChunkData::instances`].
+    pub variants: Vec<Range<u32>>,
+    pub usage: GrassDataUsage,
+}
 
-    fn extract_asset(&self) -> Self::ExtractedAsset {
-        GrassChunkData(self.0.clone())
+/// Grass instances added, changed or removed in the main world since the last extraction.
+#[derive(Resource, Default)]
+pub struct ExtractedGrassChunkData {
+    extracted: Vec<(AssetId<GrassChunkData>, GrassChunkData)>,
+    removed: Vec<AssetId<GrassChunkData>>,
+}
+
+/// Instance buffers of every [`GrassChunkData`], drawn by the grass and scatter draw commands.
+#[derive(Resource, Deref, DerefMut, Default)]
+pub struct RenderGrassChunkData(pub HashMap<AssetId<GrassChunkData>, GrassChunkBuffer>);
+
+/// Extracts the changed [`GrassChunkData`], taking the instances out of the main world ones used only for rendering.
+pub(crate) fn extract_grass_chunk_data(
+    mut commands: Commands,
+    mut main_world: ResMut<MainWorld>,
+    mut events: Local<ManualEventReader<AssetEvent<GrassChunkData>>>,
+    mut moved: Local<HashSet<AssetId<GrassChunkData>>>,
+) {
+    let mut changed = HashSet::new();
+    let mut removed = Vec::new();
+    for event in events.read(main_world.resource::<Events<AssetEvent<GrassChunkData>>>()) {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                changed.insert(*id);
+            }
+            AssetEvent::Removed { id } => {
+                changed.remove(id);
+                moved.remove(id);
+                removed.push(*id);
+            }
+            AssetEvent::LoadedWithDependencies { .. } => {}
+        }
     }
 
-    fn prepare_asset(
-            extracted_asset: Self::ExtractedAsset,
-            param: &mut SystemParamItem<Self::Param>,
-        ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
-        let render_device = param;
+    // the moved instances are already on the GPU, this also skips the `Modified` event sent by moving them
+    changed.retain(|id| !moved.contains(id));
+
+    let mut assets = main_world.resource_mut::<Assets<GrassChunkData>>();
+    let extracted = changed
+        .into_iter()
+        .filter_map(|id| {
+            let data = match assets.get(id)?.usage {
+                GrassDataUsage::MainAndRenderWorld => assets.get(id)?.clone(),
+                GrassDataUsage::RenderWorld => {
+                    moved.insert(id);
+                    let data = assets.get_mut(id)?;
+                    GrassChunkData {
+                        instances: std::mem::take(&mut data.instances),
+                        variants: data.variants.clone(),
+                        usage: data.usage,
+                    }
+                }
+            };
+            Some((id, data))
+        })
+        .collect();
+
+    commands.insert_resource(ExtractedGrassChunkData { extracted, removed });
+}
 
+pub(crate) fn prepare_grass_chunk_data(
+    mut extracted: ResMut<ExtractedGrassChunkData>,
+    mut chunk_data: ResMut<RenderGrassChunkData>,
+    render_device: Res<RenderDevice>,
+    cull_pipeline: Res<GrassCullPipeline>,
+    config: Res<GrassConfig>,
+) {
+    for id in std::mem::take(&mut extracted.removed) {
+        chunk_data.remove(&id);
+    }
+
+    for (id, GrassChunkData { instances, variants, .. }) in extracted.extracted.drain(..) {
         let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
             label: None,
-            contents:  bytemuck::cast_slice(extracted_asset.as_slice()),
+            contents:  bytemuck::cast_slice(instances.as_slice()),
             usage: BufferUsages::VERTEX | BufferUsages::COPY_DST | BufferUsages::STORAGE
         });
 
-        Ok(GrassChunkBuffer {
+        chunk_data.insert(id, GrassChunkBuffer {
             buffer,
-            length: extracted_asset.len(),
-        })
+            length: instances.len(),
+            variants,
+            cull: None,
+        });
+    }
+
+    // the cull buffers follow GrassConfig::gpu_culling, so it can be toggled without regenerating the grass
+    for chunk_buffer in chunk_data.values_mut() {
+        // an empty buffer can't be bound as storage, grass without blades has nothing to cull
+        if !config.gpu_culling || chunk_buffer.length == 0 {
+            chunk_buffer.cull = None;
+        } else if chunk_buffer.cull.is_none() {
+            chunk_buffer.cull = Some(GrassCullBuffer::new(&render_device, &cull_pipeline, &chunk_buffer.buffer, &chunk_buffer.variants));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `bevy_pbr::utils::octahedral_decode` of the quantized normal, as in `grass.wgsl`.
+    fn decode_normal(data: &GrassData) -> Vec3 {
+        let f = Vec2::new((data.normal & 0xff) as f32, (data.normal >> 8) as f32) / 255.0 * 2.0 - 1.0;
+        let n = Vec3::new(f.x, f.y, 1.0 - f.x.abs() - f.y.abs());
+        let t = (-n.z).clamp(0.0, 1.0);
+        let w = Vec2::select(n.xy().cmpge(Vec2::ZERO), Vec2::splat(-t), Vec2::splat(t));
+        (n.xy() + w).extend(n.z).normalize()
+    }
+
+    fn grass_data(chunk: (i32, i32, i32), chunk_uvw: Vec3, normal: Vec3) -> GrassData {
+        GrassData::new(chunk, chunk_uvw, normal, Color::WHITE, 0, 1.0, 0)
+    }
+
+    #[test]
+    fn position_round_trips() {
+        let chunk_size = 30.0;
+        for (chunk, world) in [
+            ((0, 0, 0), Vec3::new(1.5, 2.0, 29.0)),
+            ((2, 0, 1), Vec3::new(75.0, 10.0, 45.25)),
+            ((-1, -2, -3), Vec3::new(-12.5, -40.0, -89.0)),
+        ] {
+            let origin = Vec3::new(chunk.0 as f32, chunk.1 as f32, chunk.2 as f32) * chunk_size;
+            let data = grass_data(chunk, (world - origin) / chunk_size, Vec3::Y);
+            let position = data.world_position(chunk_size);
+
+            assert_eq!(data.chunk, [chunk.0 as i16, chunk.1 as i16, chunk.2 as i16]);
+            assert!(position.abs_diff_eq(world, chunk_size / u16::MAX as f32), "{position} != {world}");
+        }
+    }
+
+    #[test]
+    fn normal_round_trips() {
+        for normal in [
+            Vec3::Y,
+            Vec3::NEG_Y,
+            Vec3::new(0.3, 0.9, -0.2).normalize(),
+            Vec3::new(0.5, -0.7, 0.4).normalize(),
+            Vec3::new(-0.6, -0.3, -0.7).normalize(),
+        ] {
+            let decoded = decode_normal(&grass_data((0, 0, 0), Vec3::ZERO, normal));
+            assert!(decoded.dot(normal) > 0.999, "{decoded} != {normal}");
+        }
+    }
+
+    #[test]
+    fn tint_is_packed_as_565() {
+        let tint = |color| GrassData::new((0, 0, 0), Vec3::ZERO, Vec3::Y, color, 0, 1.0, 0).tint;
+
+        assert_eq!(tint(Color::WHITE), 0xffff);
+        assert_eq!(tint(Color::BLACK), 0);
+        assert_eq!(tint(Color::rgb(1.0, 0.0, 0.0)), 31 << 11);
+        assert_eq!(tint(Color::rgb(0.0, 1.0, 0.0)), 63 << 5);
+        assert_eq!(tint(Color::rgb(0.0, 0.0, 1.0)), 31);
+        assert_eq!(tint(Color::rgb(0.5, 0.25, 0.75)), 16 << 11 | 16 << 5 | 23);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn seed_height_and_blade_type_are_packed() {
+        let data = GrassData::new((0, 0, 0), Vec3::ZERO, Vec3::Y, Color::WHITE, 0xbeef, 0.5, 7);
+
+        assert_eq!(data.packed & 0xffff, 0xbeef);
+        assert_eq!((data.packed >> 16) & 0xff, 128);
+        assert_eq!(data.blade_type(), 7);
+
+        let data = GrassData::new((0, 0, 0), Vec3::ZERO, Vec3::Y, Color::WHITE, u16::MAX, 2.0, u8::MAX);
+        assert_eq!(data.packed, u32::MAX);
+    }
+}
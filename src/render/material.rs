@@ -0,0 +1,88 @@
+use bevy::{prelude::*, render::{render_resource::{AsBindGroupError, PreparedBindGroup}, renderer::RenderDevice, render_asset::RenderAssets, texture::FallbackImage, Extract}, utils::{HashMap, HashSet}};
+
+use crate::grass::material::GrassMaterial;
+
+use super::pipeline::GrassMaterialPipeline;
+
+/// Grass materials added, changed or removed in the main world since the last extraction.
+#[derive(Resource)]
+pub struct ExtractedGrassMaterials<M: GrassMaterial> {
+    extracted: Vec<(AssetId<M>, M)>,
+    removed: Vec<AssetId<M>>,
+}
+
+impl<M: GrassMaterial> Default for ExtractedGrassMaterials<M> {
+    fn default() -> Self {
+        Self {
+            extracted: Vec::new(),
+            removed: Vec::new(),
+        }
+    }
+}
+
+/// Material bind groups, bound at group 1 of the grass pipelines.
+#[derive(Resource, Deref, DerefMut)]
+pub struct RenderGrassMaterials<M: GrassMaterial>(pub HashMap<AssetId<M>, PreparedBindGroup<M::Data>>);
+
+impl<M: GrassMaterial> Default for RenderGrassMaterials<M> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+pub(crate) fn extract_grass_materials<M: GrassMaterial>(
+    mut commands: Commands,
+    mut events: Extract<EventReader<AssetEvent<M>>>,
+    assets: Extract<Res<Assets<M>>>,
+) {
+    let mut changed = HashSet::new();
+    let mut removed = Vec::new();
+    for event in events.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                changed.insert(*id);
+            }
+            AssetEvent::Removed { id } => {
+                changed.remove(id);
+                removed.push(*id);
+            }
+            AssetEvent::LoadedWithDependencies { .. } => {}
+        }
+    }
+
+    let extracted = changed
+        .into_iter()
+        .filter_map(|id| assets.get(id).map(|material| (id, material.clone())))
+        .collect();
+
+    commands.insert_resource(ExtractedGrassMaterials { extracted, removed });
+}
+
+pub(crate) fn prepare_grass_materials<M: GrassMaterial>(
+    mut extracted: ResMut<ExtractedGrassMaterials<M>>,
+    mut pending: Local<Vec<(AssetId<M>, M)>>,
+    mut materials: ResMut<RenderGrassMaterials<M>>,
+    render_device: Res<RenderDevice>,
+    images: Res<RenderAssets<Image>>,
+    fallback_image: Res<FallbackImage>,
+    pipeline: Res<GrassMaterialPipeline<M>>,
+) {
+    let removed = std::mem::take(&mut extracted.removed);
+    pending.retain(|(id, _)| !removed.contains(id));
+    for id in removed {
+        materials.remove(&id);
+    }
+
+    // materials whose textures weren't loaded yet are retried every frame
+    let queued = std::mem::take(&mut *pending);
+    for (id, material) in queued.into_iter().chain(extracted.extracted.drain(..)) {
+        match material.as_bind_group(&pipeline.material_layout, &render_device, &images, &fallback_image) {
+            Ok(prepared) => {
+                materials.insert(id, prepared);
+            }
+            Err(AsBindGroupError::RetryNextUpdate) => {
+                pending.push((id, material));
+            }
+        }
+    }
+}
@@ -2,5 +2,7 @@ pub mod pipeline;
 pub mod queue;
 pub mod prepare;
 pub mod draw;
+pub mod node;
+pub mod material;
 
-pub mod instance;
\ No newline at end of file
+pub mod instance;
@@ -0,0 +1,95 @@
+use std::num::NonZeroU64;
+
+use bevy::{prelude::*, render::{render_graph::{ViewNode, RenderGraphContext, NodeRunError}, renderer::RenderContext, render_resource::{PipelineCache, ComputePassDescriptor}}, ecs::query::QueryItem, utils::HashSet};
+
+use crate::grass::{chunk::RenderGrassChunks, grass::Grass, wind::GrassWind, config::GrassConfig};
+
+use super::{prepare::{BufferBindGroup, GrassCullView}, pipeline::GrassCullPipeline, instance::RenderGrassChunkData, draw::INDIRECT_ARGS_SIZE};
+
+pub(crate) const GRASS_CULL_NODE: &str = "grass_cull";
+
+const WORKGROUP_SIZE: u32 = 64;
+
+/// The grass entities to cull, with their grass and local wind bind groups.
+type GrassCullQuery = (&'static RenderGrassChunks, &'static BufferBindGroup<Grass>, Option<&'static BufferBindGroup<GrassWind>>);
+
+pub(crate) struct GrassCullNode {
+    query: QueryState<GrassCullQuery>,
+}
+
+impl FromWorld for GrassCullNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            query: world.query(),
+        }
+    }
+}
+
+impl ViewNode for GrassCullNode {
+    type ViewQuery = &'static BufferBindGroup<GrassCullView>;
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        view_bind_group: QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        if !world.resource::<GrassConfig>().gpu_culling {
+            return Ok(());
+        }
+
+        let cull_pipeline = world.resource::<GrassCullPipeline>();
+        let Some(pipeline) = world.resource::<PipelineCache>().get_compute_pipeline(cull_pipeline.pipeline_id) else {
+            return Ok(());
+        };
+        let Some(global_wind) = world.get_resource::<BufferBindGroup<GrassWind>>() else {
+            return Ok(());
+        };
+        let grass_data = world.resource::<RenderGrassChunkData>();
+
+        // every blade of the grass is culled at once, the chunk ranges are only used by the CPU culled draws
+        let mut culled = HashSet::new();
+        let mut dispatches = Vec::new();
+        for (chunks, grass_bind_group, local_wind) in self.query.iter_manual(world) {
+            let wind_bind_group = local_wind.unwrap_or(global_wind);
+            if chunks.ranges.is_empty() || !culled.insert(chunks.instances) {
+                continue;
+            }
+            let Some(gpu_grass) = grass_data.get(&chunks.instances) else {
+                continue;
+            };
+            let Some(cull) = &gpu_grass.cull else {
+                continue;
+            };
+            dispatches.push((gpu_grass.length as u32, gpu_grass.variants.len(), cull, grass_bind_group, wind_bind_group));
+        }
+
+        // reset the instance count of the high and low LOD draws of every variant
+        let command_encoder = render_context.command_encoder();
+        for (_, variants, cull, _, _) in dispatches.iter() {
+            for draw in 0..2 * variants {
+                command_encoder.clear_buffer(&cull.indirect_buffer, (draw * INDIRECT_ARGS_SIZE + 4) as u64, NonZeroU64::new(4));
+            }
+        }
+
+        let mut pass = command_encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("grass_cull_pass"),
+        });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &view_bind_group.bind_group, &[]);
+
+        for (length, _, cull, grass_bind_group, wind_bind_group) in dispatches {
+            pass.set_bind_group(1, &cull.bind_group, &[]);
+            pass.set_bind_group(2, &grass_bind_group.bind_group, &[]);
+            pass.set_bind_group(3, &wind_bind_group.bind_group, &[]);
+            pass.dispatch_workgroups(length.div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+
+        Ok(())
+    }
+}
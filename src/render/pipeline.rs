@@ -1,13 +1,17 @@
-use bevy::{prelude::*, pbr::{MeshPipeline, MeshPipelineKey}, render::{render_resource::{BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, BufferBindingType, SpecializedMeshPipeline, RenderPipelineDescriptor, SpecializedMeshPipelineError, VertexBufferLayout, VertexStepMode, VertexAttribute, VertexFormat, TextureSampleType, TextureViewDimension}, renderer::RenderDevice, mesh::MeshVertexBufferLayout}};
+use std::{hash::Hash, marker::PhantomData};
 
-use crate::GRASS_SHADER_HANDLE;
+use bevy::{prelude::*, pbr::{MeshPipeline, MeshPipelineKey, PrepassPipeline, MaterialPipeline, MaterialPipelineKey, MeshUniform, MeshTransforms, MeshFlags}, math::{Affine3, Affine3A}, render::{render_resource::{GpuArrayBuffer, BindGroupLayout, BindGroupLayoutDescriptor, BindGroupLayoutEntry, ShaderStages, BindingType, BufferBindingType, SpecializedMeshPipeline, RenderPipelineDescriptor, SpecializedMeshPipelineError, VertexBufferLayout, VertexStepMode, VertexAttribute, VertexFormat, TextureSampleType, TextureViewDimension, SamplerBindingType, CachedComputePipelineId, ComputePipelineDescriptor, PipelineCache, VertexState, FragmentState, PrimitiveState, FrontFace, PolygonMode, DepthStencilState, CompareFunction, StencilState, DepthBiasState, MultisampleState, ShaderDefVal, ColorTargetState, ColorWrites, ShaderRef}, renderer::{RenderDevice, RenderQueue}, mesh::MeshVertexBufferLayout}, core_pipeline::{core_3d::CORE_3D_DEPTH_FORMAT, prepass::{NORMAL_PREPASS_FORMAT, MOTION_VECTOR_PREPASS_FORMAT}, deferred::{DEFERRED_PREPASS_FORMAT, DEFERRED_LIGHTING_PASS_ID_FORMAT}}};
+
+use crate::{GRASS_SHADER_HANDLE, GRASS_CULL_SHADER_HANDLE, SCATTER_SHADER_HANDLE, grass::{grass::{GrassShading, MAX_GRASS_VARIANTS}, wind::{MAX_WIND_ZONES, MAX_WIND_IMPULSES}, material::{GrassMaterial, GrassMaterialPipelineKey}}};
 
 use super::instance::GrassData;
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct GrassPipeline {
     shader: Handle<Shader>,
     mesh_pipeline: MeshPipeline,
+    prepass_view_layout_motion_vectors: BindGroupLayout,
+    prepass_view_layout_no_motion_vectors: BindGroupLayout,
     pub grass_layout: BindGroupLayout,
     pub wind_layout: BindGroupLayout,
 }
@@ -17,6 +21,9 @@ impl FromWorld for GrassPipeline {
         let render_device = world.get_resource::<RenderDevice>().unwrap();
 
         let mesh_pipeline = world.resource::<MeshPipeline>();
+        let prepass_pipeline = world.resource::<PrepassPipeline<StandardMaterial>>();
+        let prepass_view_layout_motion_vectors = prepass_pipeline.view_layout_motion_vectors.clone();
+        let prepass_view_layout_no_motion_vectors = prepass_pipeline.view_layout_no_motion_vectors.clone();
 
         let grass_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("grass_layout"),
@@ -33,6 +40,55 @@ impl FromWorld for GrassPipeline {
                 },
                 BindGroupLayoutEntry {
                     binding: 1,
+                    visibility: ShaderStages::VERTEX_FRAGMENT | ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // chunk size, to decode the instance positions
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX | ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // GrassSeason value and color ramp
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                // GrassCoverage, the mask is sampled at the blade roots
+                BindGroupLayoutEntry {
+                    binding: 6,
                     visibility: ShaderStages::VERTEX_FRAGMENT,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
@@ -40,7 +96,23 @@ impl FromWorld for GrassPipeline {
                         min_binding_size: None,
                     },
                     count: None,
-                }
+                },
+                BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
             ]
         });
 
@@ -49,7 +121,7 @@ impl FromWorld for GrassPipeline {
             entries: &[
                 BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: ShaderStages::VERTEX,
+                    visibility: ShaderStages::VERTEX | ShaderStages::COMPUTE,
                     ty: BindingType::Buffer {
                         ty: BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -67,60 +139,482 @@ impl FromWorld for GrassPipeline {
                     },
                     count: None,
                 },
+                // previous frame's wind for motion vectors
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // WindGusts, WindZones and WindImpulses, the culling allows for their sway
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::VERTEX | ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ]
         });
 
         GrassPipeline {
             shader: GRASS_SHADER_HANDLE,
             mesh_pipeline: mesh_pipeline.clone(),
+            prepass_view_layout_motion_vectors,
+            prepass_view_layout_no_motion_vectors,
             grass_layout,
             wind_layout,
         }
     }
 }
 
-impl SpecializedMeshPipeline for GrassPipeline {
-    type Key = MeshPipelineKey;
-
-    fn specialize(
-        &self,
-        key: Self::Key,
-        layout: &MeshVertexBufferLayout,
-    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
-        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
-
-        descriptor
-            .vertex
-            .shader_defs
-            .push("MESH_BINDGROUP_1".into());
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GrassPipelineKey {
+    pub mesh_key: MeshPipelineKey,
+    /// Specialize for the prepass, deferred and shadow passes instead of the forward pass.
+    pub prepass: bool,
+    pub shading: GrassShading,
+}
 
-        descriptor.vertex.shader = self.shader.clone();
-        descriptor.vertex.buffers.push(VertexBufferLayout {
+impl GrassPipeline {
+    /// [`GrassData`] instance attributes, at `start_location` and the two locations after it.
+    pub(crate) fn instance_layout(start_location: u32) -> VertexBufferLayout {
+        VertexBufferLayout {
             array_stride: std::mem::size_of::<GrassData>() as u64,
             step_mode: VertexStepMode::Instance,
             attributes: vec![
+                // position and normal
                 VertexAttribute {
-                    format: VertexFormat::Float32x3,
+                    format: VertexFormat::Uint16x4,
                     offset: 0,
-                    shader_location: 3,
+                    shader_location: start_location,
                 },
+                // chunk
                 VertexAttribute {
-                    format: VertexFormat::Float32x3,
-                    offset: std::mem::size_of::<[f32; 3]>() as u64,
-                    shader_location: 4,
+                    format: VertexFormat::Sint16x4,
+                    offset: std::mem::size_of::<[u16; 4]>() as u64,
+                    shader_location: start_location + 1,
                 },
+                // seed, height scale and blade type
                 VertexAttribute {
-                    format: VertexFormat::Float32x3,
-                    offset: std::mem::size_of::<[f32; 6]>() as u64,
-                    shader_location: 5,
+                    format: VertexFormat::Uint32,
+                    offset: std::mem::size_of::<[u16; 8]>() as u64,
+                    shader_location: start_location + 2,
                 },
             ],
+        }
+    }
+
+    fn specialize_prepass(
+        &self,
+        key: MeshPipelineKey,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut shader_defs: Vec<ShaderDefVal> = vec![
+            "PREPASS_PIPELINE".into(),
+            ShaderDefVal::UInt("MAX_GRASS_VARIANTS".into(), MAX_GRASS_VARIANTS as u32),
+            ShaderDefVal::UInt("MAX_WIND_ZONES".into(), MAX_WIND_ZONES as u32),
+            ShaderDefVal::UInt("MAX_WIND_IMPULSES".into(), MAX_WIND_IMPULSES as u32),
+        ];
+
+        if key.contains(MeshPipelineKey::DEPTH_CLAMP_ORTHO) {
+            shader_defs.push("DEPTH_CLAMP_ORTHO".into());
+            shader_defs.push("PREPASS_FRAGMENT".into());
+        }
+        if key.contains(MeshPipelineKey::NORMAL_PREPASS) {
+            shader_defs.push("NORMAL_PREPASS".into());
+        }
+        if key.contains(MeshPipelineKey::MOTION_VECTOR_PREPASS) {
+            shader_defs.push("MOTION_VECTOR_PREPASS".into());
+        }
+        if key.contains(MeshPipelineKey::DEFERRED_PREPASS) {
+            // the gbuffer is lit by the deferred PBR lighting pass
+            shader_defs.push("DEFERRED_PREPASS".into());
+            shader_defs.push("GRASS_PBR".into());
+        }
+        if key.intersects(
+            MeshPipelineKey::NORMAL_PREPASS
+                | MeshPipelineKey::MOTION_VECTOR_PREPASS
+                | MeshPipelineKey::DEFERRED_PREPASS,
+        ) {
+            shader_defs.push("PREPASS_FRAGMENT".into());
+        }
+
+        let vertex_buffer_layout = layout.get_layout(&[
+            Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+            Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+        ])?;
+
+        // same target slots as the prepass render pass: normals, motion vectors, then the deferred gbuffer
+        let target = |flag: MeshPipelineKey, format| key.contains(flag).then_some(ColorTargetState {
+            format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
         });
+        let mut targets = vec![
+            target(MeshPipelineKey::NORMAL_PREPASS, NORMAL_PREPASS_FORMAT),
+            target(MeshPipelineKey::MOTION_VECTOR_PREPASS, MOTION_VECTOR_PREPASS_FORMAT),
+            target(MeshPipelineKey::DEFERRED_PREPASS, DEFERRED_PREPASS_FORMAT),
+            target(MeshPipelineKey::DEFERRED_PREPASS, DEFERRED_LIGHTING_PASS_ID_FORMAT),
+        ];
+        if targets.iter().all(Option::is_none) {
+            targets.clear();
+        }
+
+        let fragment = (!targets.is_empty() || key.contains(MeshPipelineKey::DEPTH_CLAMP_ORTHO)).then(|| FragmentState {
+            shader: self.shader.clone(),
+            shader_defs: shader_defs.clone(),
+            entry_point: "fragment".into(),
+            targets,
+        });
+
+        let view_layout = if key.contains(MeshPipelineKey::MOTION_VECTOR_PREPASS) {
+            self.prepass_view_layout_motion_vectors.clone()
+        } else {
+            self.prepass_view_layout_no_motion_vectors.clone()
+        };
+
+        Ok(RenderPipelineDescriptor {
+            label: Some("grass_prepass_pipeline".into()),
+            layout: vec![
+                view_layout,
+                self.mesh_pipeline.mesh_layouts.model_only.clone(),
+                self.grass_layout.clone(),
+                self.wind_layout.clone(),
+            ],
+            push_constant_ranges: Vec::new(),
+            vertex: VertexState {
+                shader: self.shader.clone(),
+                shader_defs,
+                entry_point: "vertex".into(),
+                buffers: vec![vertex_buffer_layout, Self::instance_layout(3)],
+            },
+            fragment,
+            primitive: PrimitiveState {
+                topology: key.primitive_topology(),
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: CORE_3D_DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: key.msaa_samples(),
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+        })
+    }
+}
+
+impl GrassPipeline {
+    /// Specializes the built-in grass shader, [`GrassMaterialPipeline`] then swaps in the material layout and shaders.
+    pub fn specialize(
+        &self,
+        key: GrassPipelineKey,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        if key.prepass {
+            return self.specialize_prepass(key.mesh_key, layout);
+        }
+
+        let mut descriptor = self.mesh_pipeline.specialize(key.mesh_key, layout)?;
+
+        let max_variants = ShaderDefVal::UInt("MAX_GRASS_VARIANTS".into(), MAX_GRASS_VARIANTS as u32);
+        descriptor.vertex.shader_defs.push(max_variants.clone());
+        descriptor.vertex.shader_defs.push(ShaderDefVal::UInt("MAX_WIND_ZONES".into(), MAX_WIND_ZONES as u32));
+        descriptor.vertex.shader_defs.push(ShaderDefVal::UInt("MAX_WIND_IMPULSES".into(), MAX_WIND_IMPULSES as u32));
+
+        let fragment = descriptor.fragment.as_mut().unwrap();
+        fragment.shader_defs.push(max_variants);
+        if key.shading == GrassShading::Pbr {
+            fragment.shader_defs.push("GRASS_PBR".into());
+        }
+        fragment.shader = self.shader.clone();
+
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.vertex.buffers.push(Self::instance_layout(3));
         descriptor.layout.push(self.grass_layout.clone());
         descriptor.layout.push(self.wind_layout.clone());
 
-        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
         descriptor.primitive.cull_mode = None;
         Ok(descriptor)
     }
-}
\ No newline at end of file
+}
+
+/// [`GrassPipeline`] with the bind group layout and shaders of the grass material `M`.
+#[derive(Resource)]
+pub struct GrassMaterialPipeline<M: GrassMaterial> {
+    pub grass_pipeline: GrassPipeline,
+    pub material_layout: BindGroupLayout,
+    vertex_shader: Option<Handle<Shader>>,
+    fragment_shader: Option<Handle<Shader>>,
+    prepass_vertex_shader: Option<Handle<Shader>>,
+    prepass_fragment_shader: Option<Handle<Shader>>,
+    marker: PhantomData<M>,
+}
+
+impl<M: GrassMaterial> FromWorld for GrassMaterialPipeline<M> {
+    fn from_world(world: &mut World) -> Self {
+        let asset_server = world.resource::<AssetServer>();
+        let render_device = world.resource::<RenderDevice>();
+
+        let load = |shader: ShaderRef| match shader {
+            ShaderRef::Default => None,
+            ShaderRef::Handle(handle) => Some(handle),
+            ShaderRef::Path(path) => Some(asset_server.load(path)),
+        };
+
+        GrassMaterialPipeline {
+            grass_pipeline: world.resource::<GrassPipeline>().clone(),
+            material_layout: M::bind_group_layout(render_device),
+            vertex_shader: load(M::vertex_shader()),
+            fragment_shader: load(M::fragment_shader()),
+            prepass_vertex_shader: load(M::prepass_vertex_shader()),
+            prepass_fragment_shader: load(M::prepass_fragment_shader()),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<M: GrassMaterial> SpecializedMeshPipeline for GrassMaterialPipeline<M>
+where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    type Key = GrassMaterialPipelineKey<M>;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.grass_pipeline.specialize(key.grass_key, layout)?;
+
+        let (vertex_shader, fragment_shader) = match key.grass_key.prepass {
+            true => (&self.prepass_vertex_shader, &self.prepass_fragment_shader),
+            false => (&self.vertex_shader, &self.fragment_shader),
+        };
+        if let Some(shader) = vertex_shader {
+            descriptor.vertex.shader = shader.clone();
+        }
+        if let (Some(fragment), Some(shader)) = (descriptor.fragment.as_mut(), fragment_shader) {
+            fragment.shader = shader.clone();
+        }
+
+        // the blades don't use the mesh bind group, so the material takes its place
+        descriptor.layout[1] = self.material_layout.clone();
+
+        M::specialize(&mut descriptor, layout, key)?;
+        Ok(descriptor)
+    }
+}
+
+/// [`MaterialPipeline<StandardMaterial>`] placing the mesh at the [`GrassData`] instances of a
+/// [`Scatter`](crate::grass::scatter::Scatter) instead of the entity transform.
+#[derive(Resource)]
+pub struct ScatterPipeline {
+    material_pipeline: MaterialPipeline<StandardMaterial>,
+    pub scatter_layout: BindGroupLayout,
+    pub wind_layout: BindGroupLayout,
+}
+
+impl FromWorld for ScatterPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let scatter_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("scatter_layout"),
+            entries: &[
+                // takes the place of the mesh uniforms, the PBR fragment shader reads the flags of instance 0
+                GpuArrayBuffer::<MeshUniform>::binding_layout(0, ShaderStages::VERTEX_FRAGMENT, render_device),
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ]
+        });
+
+        ScatterPipeline {
+            material_pipeline: world.resource::<MaterialPipeline<StandardMaterial>>().clone(),
+            scatter_layout,
+            wind_layout: world.resource::<GrassPipeline>().wind_layout.clone(),
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for ScatterPipeline {
+    type Key = MaterialPipelineKey<StandardMaterial>;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayout,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.material_pipeline.specialize(key, layout)?;
+
+        descriptor.vertex.shader = SCATTER_SHADER_HANDLE;
+        descriptor.vertex.shader_defs.push(ShaderDefVal::UInt("MAX_WIND_ZONES".into(), MAX_WIND_ZONES as u32));
+        descriptor.vertex.shader_defs.push(ShaderDefVal::UInt("MAX_WIND_IMPULSES".into(), MAX_WIND_IMPULSES as u32));
+        descriptor.vertex.buffers.push(GrassPipeline::instance_layout(8));
+        descriptor.layout[2] = self.scatter_layout.clone();
+        descriptor.layout.push(self.wind_layout.clone());
+
+        // the scatter isn't drawn in the prepass, so the prepass normals belong to whatever is behind it
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            fragment.shader_defs.retain(|def| *def != "LOAD_PREPASS_NORMALS".into());
+        }
+
+        Ok(descriptor)
+    }
+}
+
+/// The single identity [`MeshUniform`] of every scatter, the instances are already in world space.
+#[derive(Resource)]
+pub struct ScatterMeshUniform {
+    pub buffer: GpuArrayBuffer<MeshUniform>,
+    pub dynamic_offset: Option<u32>,
+}
+
+impl FromWorld for ScatterMeshUniform {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let render_queue = world.resource::<RenderQueue>();
+
+        let mut buffer = GpuArrayBuffer::new(render_device);
+        let index = buffer.push(MeshUniform::from(&MeshTransforms {
+            transform: Affine3::from(&Affine3A::IDENTITY),
+            previous_transform: Affine3::from(&Affine3A::IDENTITY),
+            flags: (MeshFlags::SHADOW_RECEIVER | MeshFlags::SIGN_DETERMINANT_MODEL_3X3).bits(),
+        }));
+        buffer.write_buffer(render_device, render_queue);
+
+        ScatterMeshUniform {
+            buffer,
+            dynamic_offset: index.dynamic_offset.map(|offset| offset.get()),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct GrassCullPipeline {
+    pub view_layout: BindGroupLayout,
+    pub chunk_layout: BindGroupLayout,
+    pub pipeline_id: CachedComputePipelineId,
+}
+
+impl FromWorld for GrassCullPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let grass_pipeline = world.resource::<GrassPipeline>();
+
+        let view_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("grass_cull_view_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ]
+        });
+
+        let chunk_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("grass_cull_chunk_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // first instance of each variant
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ]
+        });
+
+        let layout = vec![
+            view_layout.clone(),
+            chunk_layout.clone(),
+            grass_pipeline.grass_layout.clone(),
+            grass_pipeline.wind_layout.clone(),
+        ];
+
+        let pipeline_id = world.resource::<PipelineCache>().queue_compute_pipeline(ComputePipelineDescriptor {
+            label: Some("grass_cull_pipeline".into()),
+            layout,
+            push_constant_ranges: Vec::new(),
+            shader: GRASS_CULL_SHADER_HANDLE,
+            shader_defs: vec![
+                ShaderDefVal::UInt("MAX_GRASS_VARIANTS".into(), MAX_GRASS_VARIANTS as u32),
+                ShaderDefVal::UInt("MAX_WIND_ZONES".into(), MAX_WIND_ZONES as u32),
+                ShaderDefVal::UInt("MAX_WIND_IMPULSES".into(), MAX_WIND_IMPULSES as u32),
+            ],
+            entry_point: "cull".into(),
+        });
+
+        GrassCullPipeline {
+            view_layout,
+            chunk_layout,
+            pipeline_id,
+        }
+    }
+}
@@ -1,10 +1,11 @@
 use std::marker::PhantomData;
 
-use bevy::{prelude::*, render::{render_resource::{BufferInitDescriptor, BufferUsages, BindGroup, BindingResource, BufferBinding, BindGroupEntries, Buffer}, renderer::RenderDevice, texture::FallbackImage, render_asset::RenderAssets}};
+use bevy::{prelude::*, render::{Extract, render_resource::{BufferInitDescriptor, BufferUsages, BindGroup, BindingResource, BufferBinding, BindGroupEntries, Buffer, BindGroupLayout, TextureView, TextureViewId, Sampler}, renderer::{RenderDevice, RenderQueue}, texture::FallbackImage, render_asset::RenderAssets, view::ExtractedView, camera::ExtractedCamera, primitives::Frustum, mesh::{GpuBufferInfo, GpuMesh}}, pbr::RenderMeshInstances, utils::HashMap};
+use bytemuck::{Pod, Zeroable};
 
-use crate::grass::{wind::GrassWind, grass::{Blade, GrassColor, Grass}, chunk::RenderGrassChunks};
+use crate::grass::{wind::{GrassWind, PreviousWind, Wind, WindZone, WindZoneShape, WindGusts, WindImpulse, MAX_WIND_ZONES, MAX_WIND_IMPULSES}, season::GrassSeason, coverage::GrassCoverage, grass::{Blade, BladeFacing, Grass, GrassLODMesh, RenderGrassVariants, MAX_GRASS_VARIANTS}, chunk::RenderGrassChunks, config::GrassConfig, scatter::Scatter};
 
-use super::pipeline::GrassPipeline;
+use super::{pipeline::{GrassPipeline, GrassCullPipeline, ScatterPipeline, ScatterMeshUniform}, instance::RenderGrassChunkData};
 
 #[derive(Component, Resource, Clone)]
 pub struct BufferBindGroup<T> {
@@ -21,86 +22,694 @@ impl<T> BufferBindGroup<T> {
     }
 }
 
-#[derive(Component, Clone)]
+/// [`Blade`] as laid out in the grass shaders, with the [`BladeFacing`] flattened.
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq)]
+#[repr(C)]
+pub struct BladeUniform {
+    pub length: f32,
+    pub width: f32,
+    pub tilt: f32,
+    pub tilt_variance: f32,
+    pub p1_flexibility: f32,
+    pub p2_flexibility: f32,
+    pub curve: f32,
+    pub specular: f32,
+    pub roughness: f32,
+    pub reflectance: f32,
+    pub translucency: f32,
+    pub thickness: f32,
+    pub length_variance: f32,
+    pub width_variance: f32,
+    pub flexibility_variance: f32,
+    pub curve_variance: f32,
+    pub hue_variance: f32,
+    pub brightness_variance: f32,
+    /// 0 random, 1 towards `facing_direction`, 2 towards the camera.
+    pub facing: u32,
+    pub facing_direction: f32,
+}
+
+impl From<&Blade> for BladeUniform {
+    fn from(blade: &Blade) -> Self {
+        let (facing, facing_direction) = match blade.facing {
+            BladeFacing::Random => (0, 0.0),
+            BladeFacing::Direction(direction) => (1, direction),
+            BladeFacing::Camera => (2, 0.0),
+        };
+
+        Self {
+            length: blade.length,
+            width: blade.width,
+            tilt: blade.tilt,
+            tilt_variance: blade.tilt_variance,
+            p1_flexibility: blade.p1_flexibility,
+            p2_flexibility: blade.p2_flexibility,
+            curve: blade.curve,
+            specular: blade.specular,
+            roughness: blade.roughness,
+            reflectance: blade.reflectance,
+            translucency: blade.translucency,
+            thickness: blade.thickness,
+            length_variance: blade.length_variance,
+            width_variance: blade.width_variance,
+            flexibility_variance: blade.flexibility_variance,
+            curve_variance: blade.curve_variance,
+            hue_variance: blade.hue_variance,
+            brightness_variance: blade.brightness_variance,
+            facing,
+            facing_direction,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq)]
+#[repr(C)]
+pub struct ChunksUniform {
+    pub chunk_size: f32,
+    pub _padding: [f32; 3],
+    /// The camera [`BladeFacing::Camera`] blades turn towards in every view, including the shadow views.
+    pub camera_position: Vec3,
+    pub _padding_2: f32,
+}
+
+/// Uniform buffers of a grass entity, kept across frames and only written when the values change.
+/// The colors and blades of the variants are padded to [`MAX_GRASS_VARIANTS`].
 pub struct GrassBuffer {
     pub color_buffer: Buffer,
     pub blade_buffer: Buffer,
+    pub chunks_buffer: Buffer,
+    color: [[[f32; 4]; 3]; MAX_GRASS_VARIANTS],
+    blade: [BladeUniform; MAX_GRASS_VARIANTS],
+    chunks: ChunksUniform,
+    /// Recreated when the season ramp or the coverage mask texture changes.
+    bind_group: Option<(BindGroup, [TextureViewId; 2])>,
 }
 
+/// Persistent [`GrassBuffer`]s keyed by grass entity, render world entities don't survive the frame.
+#[derive(Resource, Default)]
+pub struct GrassBuffers(pub HashMap<Entity, GrassBuffer>);
+
 pub(crate) fn prepare_grass_buffers(
+    mut buffers: ResMut<GrassBuffers>,
+    query: Query<(Entity, &RenderGrassVariants, &RenderGrassChunks)>,
+    cameras: Query<(&ExtractedCamera, &ExtractedView)>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    buffers.0.retain(|entity, _| query.contains(*entity));
+
+    // blades facing the view being drawn would face each light in its shadow view, use the first camera for every view
+    let camera_position = cameras.iter()
+        .min_by_key(|(camera, _)| camera.order)
+        .map_or(Vec3::ZERO, |(_, view)| view.transform.translation());
+
+    for (entity, variants, chunks) in &query {
+        let mut color = [[[0.0; 4]; 3]; MAX_GRASS_VARIANTS];
+        let mut blade = [BladeUniform::zeroed(); MAX_GRASS_VARIANTS];
+        for (i, (variant_color, variant_blade)) in variants.colors.iter().zip(&variants.blades).take(MAX_GRASS_VARIANTS).enumerate() {
+            color[i] = variant_color.to_array();
+            blade[i] = variant_blade.into();
+        }
+        let chunks_uniform = ChunksUniform {
+            chunk_size: chunks.chunk_size,
+            camera_position,
+            ..ChunksUniform::zeroed()
+        };
+
+        match buffers.0.get_mut(&entity) {
+            Some(buffer) => {
+                if buffer.color != color {
+                    render_queue.write_buffer(&buffer.color_buffer, 0, bytemuck::cast_slice(&color));
+                    buffer.color = color;
+                }
+                if buffer.blade != blade {
+                    render_queue.write_buffer(&buffer.blade_buffer, 0, bytemuck::cast_slice(&blade));
+                    buffer.blade = blade;
+                }
+                if buffer.chunks != chunks_uniform {
+                    render_queue.write_buffer(&buffer.chunks_buffer, 0, bytemuck::bytes_of(&chunks_uniform));
+                    buffer.chunks = chunks_uniform;
+                }
+            }
+            None => {
+                let color_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("color buffer"),
+                    contents: bytemuck::cast_slice(&color),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+                });
+
+                let blade_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("blade buffer"),
+                    contents: bytemuck::cast_slice(&blade),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+                });
+
+                let chunks_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("chunks buffer"),
+                    contents: bytemuck::bytes_of(&chunks_uniform),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+                });
+
+                buffers.0.insert(entity, GrassBuffer {
+                    color_buffer,
+                    blade_buffer,
+                    chunks_buffer,
+                    color,
+                    blade,
+                    chunks: chunks_uniform,
+                    bind_group: None,
+                });
+            }
+        }
+    }
+}
+
+pub(crate) fn prepare_grass_bind_group(
     mut commands: Commands,
-    query: Query<(Entity, &GrassColor, &Blade)>,
-    render_device: Res<RenderDevice>
+    pipeline: Res<GrassPipeline>,
+    render_device: Res<RenderDevice>,
+    mut buffers: ResMut<GrassBuffers>,
+    season_buffer: Res<SeasonBuffer>,
+    coverage_buffer: Res<CoverageBuffer>,
 ) {
-    for (entity, color, blade) in &query {
-        let color_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("color buffer"),
-            contents: bytemuck::cast_slice(&color.to_array()),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
-        });
+    let (Some((ramp_texture, ramp_sampler)), Some((mask_texture, mask_sampler))) = (&season_buffer.ramp, &coverage_buffer.mask) else {
+        return;
+    };
+    let texture_view_ids = [ramp_texture.id(), mask_texture.id()];
+
+    for (entity, grass) in buffers.0.iter_mut() {
+        // the buffers are never reallocated, so the bind group only changes with the textures
+        let bind_group = match &grass.bind_group {
+            Some((bind_group, ids)) if *ids == texture_view_ids => bind_group.clone(),
+            _ => {
+                let bind_group = render_device.create_bind_group(
+                    Some("grass bind group"),
+                    &pipeline.grass_layout,
+                    &BindGroupEntries::sequential((
+                        BufferBinding {
+                            buffer: &grass.color_buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                        BufferBinding {
+                            buffer: &grass.blade_buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                        BufferBinding {
+                            buffer: &grass.chunks_buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                        season_buffer.buffer.as_entire_binding(),
+                        ramp_texture,
+                        ramp_sampler,
+                        coverage_buffer.buffer.as_entire_binding(),
+                        mask_texture,
+                        mask_sampler,
+                    )),
+                );
+                grass.bind_group = Some((bind_group.clone(), texture_view_ids));
+                bind_group
+            }
+        };
 
-        let blade_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("blade buffer"),
-            contents: bytemuck::cast_slice(&[blade.clone()]),
+        commands.entity(*entity).insert(BufferBindGroup::<Grass>::new(bind_group));
+    }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq)]
+#[repr(C)]
+pub struct SeasonUniform {
+    pub value: f32,
+    pub variance: f32,
+    /// 0 without a ramp, so the fallback texture doesn't tint the grass.
+    pub strength: f32,
+    pub _padding: f32,
+}
+
+/// The [`GrassSeason`] uniform shared by every grass entity, only written when the season changes.
+#[derive(Resource)]
+pub struct SeasonBuffer {
+    pub buffer: Buffer,
+    /// The ramp texture, or the fallback image until it's loaded.
+    pub ramp: Option<(TextureView, Sampler)>,
+    season: SeasonUniform,
+}
+
+impl FromWorld for SeasonBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let season = SeasonUniform::zeroed();
+        let buffer = world.resource::<RenderDevice>().create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("season buffer"),
+            contents: bytemuck::bytes_of(&season),
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
         });
 
-        commands.entity(entity).insert(GrassBuffer {
-            color_buffer,
-            blade_buffer,
-        });
+        Self {
+            buffer,
+            ramp: None,
+            season,
+        }
     }
 }
 
-pub(crate) fn prepare_grass_bind_group(
+pub(crate) fn prepare_season_buffer(
+    mut season_buffer: ResMut<SeasonBuffer>,
+    season: Res<GrassSeason>,
+    images: Res<RenderAssets<Image>>,
+    fallback_img: Res<FallbackImage>,
+    render_queue: Res<RenderQueue>,
+) {
+    let ramp = season.ramp.as_ref().and_then(|ramp| images.get(ramp));
+    season_buffer.ramp = Some(match ramp {
+        Some(image) => (image.texture_view.clone(), image.sampler.clone()),
+        None => (fallback_img.d2.texture_view.clone(), fallback_img.d2.sampler.clone()),
+    });
+
+    let uniform = SeasonUniform {
+        value: season.value,
+        variance: season.variance,
+        strength: match ramp {
+            Some(_) => 1.0,
+            None => 0.0,
+        },
+        _padding: 0.0,
+    };
+
+    if season_buffer.season != uniform {
+        render_queue.write_buffer(&season_buffer.buffer, 0, bytemuck::bytes_of(&uniform));
+        season_buffer.season = uniform;
+    }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq)]
+#[repr(C)]
+pub struct ScatterUniform {
+    pub chunk_size: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    pub sway: f32,
+    pub random_rotation: u32,
+    pub align_to_normal: u32,
+    pub _padding: [u32; 2],
+}
+
+/// Uniform buffer of a scatter entity, kept across frames and only written when the values change.
+pub struct ScatterBuffer {
+    pub buffer: Buffer,
+    uniform: ScatterUniform,
+    bind_group: Option<BindGroup>,
+}
+
+/// Persistent [`ScatterBuffer`]s keyed by scatter entity.
+#[derive(Resource, Default)]
+pub struct ScatterBuffers(pub HashMap<Entity, ScatterBuffer>);
+
+pub(crate) fn prepare_scatter_buffers(
+    mut buffers: ResMut<ScatterBuffers>,
+    query: Query<(Entity, &Scatter, &RenderGrassChunks)>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    buffers.0.retain(|entity, _| query.contains(*entity));
+
+    for (entity, scatter, chunks) in &query {
+        let uniform = ScatterUniform {
+            chunk_size: chunks.chunk_size,
+            min_scale: scatter.min_scale,
+            max_scale: scatter.max_scale,
+            sway: scatter.sway,
+            random_rotation: scatter.random_rotation as u32,
+            align_to_normal: scatter.align_to_normal as u32,
+            _padding: [0; 2],
+        };
+
+        match buffers.0.get_mut(&entity) {
+            Some(buffer) => {
+                if buffer.uniform != uniform {
+                    render_queue.write_buffer(&buffer.buffer, 0, bytemuck::bytes_of(&uniform));
+                    buffer.uniform = uniform;
+                }
+            }
+            None => {
+                let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                    label: Some("scatter buffer"),
+                    contents: bytemuck::bytes_of(&uniform),
+                    usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+                });
+
+                buffers.0.insert(entity, ScatterBuffer {
+                    buffer,
+                    uniform,
+                    bind_group: None,
+                });
+            }
+        }
+    }
+}
+
+pub(crate) fn prepare_scatter_bind_group(
     mut commands: Commands,
-    pipeline: Res<GrassPipeline>,
+    pipeline: Res<ScatterPipeline>,
+    mesh_uniform: Res<ScatterMeshUniform>,
     render_device: Res<RenderDevice>,
-    query: Query<(Entity, &GrassBuffer)>, 
+    mut buffers: ResMut<ScatterBuffers>,
 ) {
-    let layout = pipeline.grass_layout.clone();
+    let Some(mesh_binding) = mesh_uniform.buffer.binding() else {
+        return;
+    };
 
-    for (entity, grass) in query.iter() {
-        let bind_group = render_device.create_bind_group(
-            Some("grass bind group"),
-            &layout,
+    for (entity, scatter) in buffers.0.iter_mut() {
+        let bind_group = scatter.bind_group.get_or_insert_with(|| render_device.create_bind_group(
+            Some("scatter bind group"),
+            &pipeline.scatter_layout,
             &BindGroupEntries::sequential((
-                BufferBinding {
-                    buffer: &grass.color_buffer,
-                    offset: 0,
-                    size: None,
-                },
-                BufferBinding {
-                    buffer: &grass.blade_buffer,
-                    offset: 0,
-                    size: None,
-                }
+                mesh_binding.clone(),
+                scatter.buffer.as_entire_binding(),
             )),
-        );
+        ));
 
-        commands.entity(entity).insert(BufferBindGroup::<Grass>::new(bind_group));
+        commands.entity(*entity).insert(BufferBindGroup::<Scatter>::new(bind_group.clone()));
     }
 }
 
-#[derive(Component, Resource, Clone)]
+#[derive(Clone, Copy, Pod, Zeroable, PartialEq)]
+#[repr(C)]
+pub struct CoverageUniform {
+    pub color: [f32; 4],
+    pub mask_min: Vec2,
+    pub mask_max: Vec2,
+    pub amount: f32,
+    pub reach: f32,
+    pub flatten: f32,
+    /// 0 without a mask, so the fallback texture doesn't hold the cover back.
+    pub masked: f32,
+}
+
+/// The [`GrassCoverage`] uniform shared by every grass entity, only written when the coverage changes.
+#[derive(Resource)]
+pub struct CoverageBuffer {
+    pub buffer: Buffer,
+    /// The mask texture, or the fallback image until it's loaded.
+    pub mask: Option<(TextureView, Sampler)>,
+    coverage: CoverageUniform,
+}
+
+impl FromWorld for CoverageBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let coverage = CoverageUniform::zeroed();
+        let buffer = world.resource::<RenderDevice>().create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("coverage buffer"),
+            contents: bytemuck::bytes_of(&coverage),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+        });
+
+        Self {
+            buffer,
+            mask: None,
+            coverage,
+        }
+    }
+}
+
+pub(crate) fn prepare_coverage_buffer(
+    mut coverage_buffer: ResMut<CoverageBuffer>,
+    coverage: Res<GrassCoverage>,
+    images: Res<RenderAssets<Image>>,
+    fallback_img: Res<FallbackImage>,
+    render_queue: Res<RenderQueue>,
+) {
+    let mask = coverage.mask.as_ref().and_then(|mask| images.get(mask));
+    coverage_buffer.mask = Some(match mask {
+        Some(image) => (image.texture_view.clone(), image.sampler.clone()),
+        None => (fallback_img.d2.texture_view.clone(), fallback_img.d2.sampler.clone()),
+    });
+
+    let uniform = CoverageUniform {
+        color: coverage.color.as_linear_rgba_f32(),
+        mask_min: coverage.mask_bounds.min,
+        mask_max: coverage.mask_bounds.max,
+        amount: coverage.amount,
+        reach: coverage.reach,
+        flatten: coverage.flatten,
+        masked: match mask {
+            Some(_) => 1.0,
+            None => 0.0,
+        },
+    };
+
+    if coverage_buffer.coverage != uniform {
+        render_queue.write_buffer(&coverage_buffer.buffer, 0, bytemuck::bytes_of(&uniform));
+        coverage_buffer.coverage = uniform;
+    }
+}
+
+/// Current and previous frame wind uniforms, kept across frames and only written when the values change.
+#[derive(Resource)]
 pub struct WindBuffer {
     buffer: Buffer,
+    previous_buffer: Buffer,
+    /// The shared [`WindFieldBuffer`], it's never reallocated.
+    field_buffer: Buffer,
+    wind: Wind,
+    previous_wind: Wind,
+    bind_group: Option<(BindGroup, TextureViewId)>,
+}
+
+impl WindBuffer {
+    fn new(render_device: &RenderDevice, field_buffer: &Buffer, wind: Wind, previous_wind: Wind) -> Self {
+        Self {
+            buffer: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("wind buffer"),
+                contents: bytemuck::bytes_of(&wind),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+            }),
+            previous_buffer: render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("previous wind buffer"),
+                contents: bytemuck::bytes_of(&previous_wind),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+            }),
+            field_buffer: field_buffer.clone(),
+            wind,
+            previous_wind,
+            bind_group: None,
+        }
+    }
+
+    fn write(&mut self, render_queue: &RenderQueue, wind: Wind, previous_wind: Wind) {
+        if bytemuck::bytes_of(&self.wind) != bytemuck::bytes_of(&wind) {
+            render_queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(&wind));
+            self.wind = wind;
+        }
+        if bytemuck::bytes_of(&self.previous_wind) != bytemuck::bytes_of(&previous_wind) {
+            render_queue.write_buffer(&self.previous_buffer, 0, bytemuck::bytes_of(&previous_wind));
+            self.previous_wind = previous_wind;
+        }
+    }
+
+    /// Returns the wind bind group, recreated only when the wind map texture changes (e.g. once it's loaded).
+    fn bind_group(&mut self, render_device: &RenderDevice, layout: &BindGroupLayout, wind_map: &TextureView) -> BindGroup {
+        match &self.bind_group {
+            Some((bind_group, texture_view_id)) if *texture_view_id == wind_map.id() => bind_group.clone(),
+            _ => {
+                let bind_group = render_device.create_bind_group(
+                    Some("wind bind group"),
+                    layout,
+                    &BindGroupEntries::sequential((
+                        BufferBinding {
+                            buffer: &self.buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                        BindingResource::TextureView(wind_map),
+                        BufferBinding {
+                            buffer: &self.previous_buffer,
+                            offset: 0,
+                            size: None,
+                        },
+                        self.field_buffer.as_entire_binding(),
+                    ))
+                );
+                self.bind_group = Some((bind_group.clone(), wind_map.id()));
+                bind_group
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct WindZoneUniform {
+    pub world_to_local: Mat4,
+    pub wind: Wind,
+    /// The sphere radius in x.
+    pub half_extents: Vec3,
+    /// 0 for a sphere, 1 for a box.
+    pub shape: u32,
+    pub falloff: f32,
+    pub blend: f32,
+    pub _padding: [f32; 2],
+}
+
+impl WindZoneUniform {
+    fn new(zone: &WindZone, transform: &GlobalTransform) -> Self {
+        let (half_extents, shape) = match zone.shape {
+            WindZoneShape::Sphere { radius } => (Vec3::new(radius, 0.0, 0.0), 0),
+            WindZoneShape::Box { half_extents } => (half_extents, 1),
+        };
+
+        Self {
+            world_to_local: transform.compute_matrix().inverse(),
+            wind: zone.wind,
+            half_extents,
+            shape,
+            falloff: zone.falloff,
+            blend: zone.blend,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct GustUniform {
+    pub travel_direction: Vec2,
+    pub offset: f32,
+    pub speed: f32,
+    pub spacing: f32,
+    pub width: f32,
+    pub strength: f32,
+    pub _padding: f32,
+}
+
+impl From<&WindGusts> for GustUniform {
+    fn from(gusts: &WindGusts) -> Self {
+        let spacing = gusts.spacing();
+        // no fronts at all, the shader would divide by the infinite spacing
+        let enabled = spacing.is_finite() && spacing > 0.0 && gusts.width > 0.0;
+
+        Self {
+            travel_direction: gusts.travel_direction(),
+            offset: gusts.offset(),
+            speed: gusts.speed.abs(),
+            spacing: if enabled { spacing } else { 1.0 },
+            width: gusts.width,
+            strength: if enabled { gusts.strength } else { 0.0 },
+            _padding: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct WindImpulseUniform {
+    pub position: Vec3,
+    pub radius: f32,
+    pub strength: f32,
+    pub falloff: f32,
+    pub front: f32,
+    /// 1 for an expanding ring at `front`.
+    pub ring: u32,
+}
+
+impl WindImpulseUniform {
+    fn new(impulse: &WindImpulse, transform: &GlobalTransform) -> Self {
+        Self {
+            position: transform.translation(),
+            radius: impulse.radius,
+            strength: impulse.current_strength(),
+            falloff: impulse.falloff,
+            front: impulse.front().unwrap_or(0.0),
+            ring: impulse.front().is_some() as u32,
+        }
+    }
+}
+
+/// `WindField` of `wind.wgsl`, keep the two in sync.
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct WindFieldUniform {
+    pub gusts: GustUniform,
+    pub zones: [WindZoneUniform; MAX_WIND_ZONES],
+    pub zone_count: u32,
+    pub impulse_count: u32,
+    pub _padding: [u32; 2],
+    pub impulses: [WindImpulseUniform; MAX_WIND_IMPULSES],
+}
+
+/// The [`WindGusts`], [`WindZone`]s and [`WindImpulse`]s, bound with every wind and only written when they change.
+#[derive(Resource)]
+pub struct WindFieldBuffer {
+    pub buffer: Buffer,
+    /// Extracted this frame.
+    field: WindFieldUniform,
+    written: WindFieldUniform,
+}
+
+impl FromWorld for WindFieldBuffer {
+    fn from_world(world: &mut World) -> Self {
+        let field = WindFieldUniform::zeroed();
+        let buffer = world.resource::<RenderDevice>().create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("wind field buffer"),
+            contents: bytemuck::bytes_of(&field),
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+        });
+
+        Self {
+            buffer,
+            field,
+            written: field,
+        }
+    }
+}
+
+pub(crate) fn extract_wind_field(
+    mut field_buffer: ResMut<WindFieldBuffer>,
+    gusts: Extract<Res<WindGusts>>,
+    zones: Extract<Query<(&WindZone, &GlobalTransform)>>,
+    impulses: Extract<Query<(&WindImpulse, &GlobalTransform)>>,
+) {
+    let mut field = WindFieldUniform::zeroed();
+    field.gusts = GustUniform::from(&**gusts);
+    for (uniform, (zone, transform)) in field.zones.iter_mut().zip(zones.iter()) {
+        *uniform = WindZoneUniform::new(zone, transform);
+        field.zone_count += 1;
+    }
+    for (uniform, (impulse, transform)) in field.impulses.iter_mut().zip(impulses.iter()) {
+        *uniform = WindImpulseUniform::new(impulse, transform);
+        field.impulse_count += 1;
+    }
+    field_buffer.field = field;
+}
+
+pub(crate) fn prepare_wind_field_buffer(
+    mut field_buffer: ResMut<WindFieldBuffer>,
+    render_queue: Res<RenderQueue>,
+) {
+    if bytemuck::bytes_of(&field_buffer.field) != bytemuck::bytes_of(&field_buffer.written) {
+        render_queue.write_buffer(&field_buffer.buffer, 0, bytemuck::bytes_of(&field_buffer.field));
+        field_buffer.written = field_buffer.field;
+    }
 }
 
+/// Persistent [`WindBuffer`]s of the grass entities with their own [`GrassWind`].
+#[derive(Resource, Default)]
+pub struct LocalWindBuffers(pub HashMap<Entity, WindBuffer>);
+
 pub(crate) fn prepare_global_wind_buffers(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
     wind: Res<GrassWind>,
+    previous_wind: Res<PreviousWind>,
+    wind_buffer: Option<ResMut<WindBuffer>>,
+    field_buffer: Res<WindFieldBuffer>,
 ) {
-    let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-        label: Some("wind buffer"),
-        contents: bytemuck::cast_slice(&[wind.wind_data.clone()]),
-        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
-    });
-
-    commands.insert_resource(WindBuffer {
-        buffer,
-    });
+    match wind_buffer {
+        Some(mut wind_buffer) => wind_buffer.write(&render_queue, wind.wind_data, previous_wind.0),
+        None => commands.insert_resource(WindBuffer::new(&render_device, &field_buffer.buffer, wind.wind_data, previous_wind.0)),
+    }
 }
 
 pub(crate) fn prepare_global_wind_bind_group(
@@ -108,11 +717,13 @@ pub(crate) fn prepare_global_wind_bind_group(
     pipeline: Res<GrassPipeline>,
     render_device: Res<RenderDevice>,
     wind: Res<GrassWind>,
-    wind_buffer: Res<WindBuffer>,
+    wind_buffer: Option<ResMut<WindBuffer>>,
     fallback_img: Res<FallbackImage>,
     images: Res<RenderAssets<Image>>,
 ) {
-    let layout = pipeline.wind_layout.clone();
+    let Some(mut wind_buffer) = wind_buffer else {
+        return;
+    };
 
     let wind_map_texture = if let Some(texture) = images.get(&wind.wind_map) {
         &texture.texture_view
@@ -120,37 +731,27 @@ pub(crate) fn prepare_global_wind_bind_group(
         &fallback_img.d2.texture_view
     };
 
-    let bind_group = render_device.create_bind_group(
-        Some("wind bind group"), 
-        &layout,
-        &BindGroupEntries::sequential((
-            BufferBinding {
-                buffer: &wind_buffer.buffer,
-                offset: 0,
-                size: None,
-            },
-            BindingResource::TextureView(&wind_map_texture)
-        ))
-    );
+    let bind_group = wind_buffer.bind_group(&render_device, &pipeline.wind_layout, wind_map_texture);
 
     commands.insert_resource(BufferBindGroup::<GrassWind>::new(bind_group));
 }
 
 pub(crate) fn prepare_local_wind_buffers(
-    mut commands: Commands,
-    query: Query<(Entity, &GrassWind)>,
+    mut buffers: ResMut<LocalWindBuffers>,
+    query: Query<(Entity, &GrassWind, &PreviousWind)>,
     render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    field_buffer: Res<WindFieldBuffer>,
 ) {
-    for (entity, grass_wind) in &query {
-        let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("local wind buffer"),
-            contents: bytemuck::cast_slice(&[grass_wind.wind_data.clone()]),
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
-        });
+    buffers.0.retain(|entity, _| query.contains(*entity));
 
-        commands.entity(entity).insert(WindBuffer {
-            buffer,
-        });
+    for (entity, grass_wind, previous_wind) in &query {
+        match buffers.0.get_mut(&entity) {
+            Some(wind_buffer) => wind_buffer.write(&render_queue, grass_wind.wind_data, previous_wind.0),
+            None => {
+                buffers.0.insert(entity, WindBuffer::new(&render_device, &field_buffer.buffer, grass_wind.wind_data, previous_wind.0));
+            }
+        }
     }
 }
 
@@ -158,32 +759,139 @@ pub(crate) fn prepare_local_wind_bind_group(
     mut commands: Commands,
     pipeline: Res<GrassPipeline>,
     render_device: Res<RenderDevice>,
-    query: Query<(Entity, &GrassWind, &WindBuffer)>,
+    mut buffers: ResMut<LocalWindBuffers>,
+    query: Query<(Entity, &GrassWind)>,
     fallback_img: Res<FallbackImage>,
     images: Res<RenderAssets<Image>>,
 ) {
-    let layout = pipeline.wind_layout.clone();
+    for (entity, grass_wind) in query.iter() {
+        let Some(wind_buffer) = buffers.0.get_mut(&entity) else {
+            continue;
+        };
 
-    for (entity, grass_wind, wind_buffer) in query.iter() {
         let wind_map_texture = if let Some(texture) = images.get(&grass_wind.wind_map) {
             &texture.texture_view
         } else {
             &fallback_img.d2.texture_view
         };
 
-        let bind_group = render_device.create_bind_group(
-            Some("local wind bind group"),
-            &layout,
-            &BindGroupEntries::sequential((
-                BufferBinding {
-                    buffer: &wind_buffer.buffer,
-                    offset: 0,
-                    size: None,
-                },
-                BindingResource::TextureView(&wind_map_texture)
-            ))
-        );
+        let bind_group = wind_buffer.bind_group(&render_device, &pipeline.wind_layout, wind_map_texture);
 
         commands.entity(entity).insert(BufferBindGroup::<GrassWind>::new(bind_group));
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy, Pod, Zeroable)]
+#[repr(C)]
+pub struct GrassCullView {
+    pub planes: [Vec4; 6],
+    pub camera_position: Vec3,
+    pub cull_distance: f32,
+    pub lod_distance: f32,
+    pub _padding: [f32; 3],
+}
+
+/// Persistent cull view uniform buffers and bind groups keyed by camera entity.
+#[derive(Resource, Default)]
+pub struct GrassCullViewBuffers(pub HashMap<Entity, (Buffer, BindGroup)>);
+
+pub(crate) fn prepare_cull_view_bind_group(
+    mut commands: Commands,
+    pipeline: Res<GrassCullPipeline>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    config: Res<GrassConfig>,
+    mut buffers: ResMut<GrassCullViewBuffers>,
+    views: Query<(Entity, &ExtractedView), With<ExtractedCamera>>,
+) {
+    if !config.gpu_culling {
+        buffers.0.clear();
+        return;
+    }
+
+    buffers.0.retain(|entity, _| views.contains(*entity));
+
+    for (entity, view) in views.iter() {
+        let view_projection = view.view_projection.unwrap_or_else(|| {
+            view.projection * view.transform.compute_matrix().inverse()
+        });
+        let frustum = Frustum::from_view_projection_custom_far(
+            &view_projection,
+            &view.transform.translation(),
+            &view.transform.back(),
+            config.cull_distance,
+        );
+
+        let cull_view = GrassCullView {
+            planes: frustum.half_spaces.map(|half_space| half_space.normal_d()),
+            camera_position: view.transform.translation(),
+            cull_distance: config.cull_distance,
+            lod_distance: config.lod_distance,
+            _padding: [0.0; 3],
+        };
+
+        // the view moves every frame, so the buffer is rewritten but never reallocated
+        let (buffer, bind_group) = buffers.0.entry(entity).or_insert_with(|| {
+            let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+                label: Some("grass cull view buffer"),
+                contents: bytemuck::cast_slice(&[cull_view]),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST
+            });
+
+            let bind_group = render_device.create_bind_group(
+                Some("grass cull view bind group"),
+                &pipeline.view_layout,
+                &BindGroupEntries::single(buffer.as_entire_binding()),
+            );
+
+            (buffer, bind_group)
+        });
+        render_queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[cull_view]));
+
+        commands.entity(entity).insert(BufferBindGroup::<GrassCullView>::new(bind_group.clone()));
+    }
+}
+
+pub(crate) fn prepare_indirect_buffers(
+    query: Query<(Entity, &RenderGrassChunks, &GrassLODMesh, &RenderGrassVariants)>,
+    config: Res<GrassConfig>,
+    meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    grass_data: Res<RenderGrassChunkData>,
+    render_queue: Res<RenderQueue>,
+) {
+    if !config.gpu_culling {
+        return;
+    }
+
+    for (entity, chunks, lod, variants) in query.iter() {
+        let Some(mesh_instance) = render_mesh_instances.get(&entity) else {
+            continue;
+        };
+        let Some(gpu_mesh_high) = meshes.get(mesh_instance.mesh_asset_id) else {
+            continue;
+        };
+        let gpu_mesh_low = lod.mesh_handle.as_ref().and_then(|handle| meshes.get(handle)).unwrap_or(gpu_mesh_high);
+
+        let Some(gpu_grass) = grass_data.get(&chunks.instances) else {
+            continue;
+        };
+        let Some(cull) = &gpu_grass.cull else {
+            continue;
+        };
+
+        // instance counts are reset and filled in by the cull pass
+        let count = |gpu_mesh: &GpuMesh| match &gpu_mesh.buffer_info {
+            GpuBufferInfo::Indexed { count, .. } => *count,
+            GpuBufferInfo::NonIndexed => gpu_mesh.vertex_count,
+        };
+        let mut args = Vec::new();
+        for gpu_mesh in [gpu_mesh_high, gpu_mesh_low] {
+            for variant in 0..gpu_grass.variants.len() {
+                let variant_mesh = variants.meshes.get(variant).copied().flatten().and_then(|mesh| meshes.get(mesh));
+                args.extend([count(variant_mesh.unwrap_or(gpu_mesh)), 0, 0, 0, 0]);
+            }
+        }
+        render_queue.write_buffer(&cull.indirect_buffer, 0, bytemuck::cast_slice(&args));
+    }
+}
@@ -1,45 +1,397 @@
-use bevy::{prelude::*, render::{render_phase::{DrawFunctions, RenderPhase}, render_resource::{SpecializedMeshPipelines, PipelineCache}, render_asset::RenderAssets, view::ExtractedView}, core_pipeline::core_3d::Opaque3d, pbr::{MeshPipelineKey, RenderMeshInstances}};
+use std::hash::Hash;
 
-use crate::grass::chunk::RenderGrassChunks;
+use bevy::{prelude::*, ecs::{query::QueryItem, system::SystemParam}, render::{render_phase::{DrawFunctions, RenderPhase}, render_resource::{SpecializedMeshPipelines, PipelineCache, CachedRenderPipelineId}, render_asset::RenderAssets, view::ExtractedView}, core_pipeline::{core_3d::{Opaque3d, AlphaMask3d, Transparent3d}, prepass::{Opaque3dPrepass, DepthPrepass, NormalPrepass, MotionVectorPrepass, DeferredPrepass}, deferred::Opaque3dDeferred, tonemapping::{Tonemapping, DebandDither}}, pbr::{MeshPipelineKey, RenderMeshInstances, RenderMeshInstance, Shadow, ViewLightEntities, LightEntity, EnvironmentMapLight, ShadowFilteringMethod, ScreenSpaceAmbientOcclusionSettings, RenderMaterials, MaterialPipelineKey}};
 
-use super::{pipeline::GrassPipeline, draw::DrawGrass};
+use crate::grass::{chunk::RenderGrassChunks, grass::{GrassShadowCaster, GrassShading}, material::{GrassMaterial, GrassMaterialPipelineKey}, scatter::Scatter};
 
-pub(crate) fn grass_queue(
+use super::{pipeline::{GrassMaterialPipeline, GrassPipelineKey, ScatterPipeline}, draw::{DrawGrass, DrawGrassShadow, DrawGrassPrepass, DrawScatter}, material::RenderGrassMaterials};
+
+/// Specializes the pipelines of the grass entities, shared by the grass queues.
+#[derive(SystemParam)]
+pub(crate) struct GrassSpecializer<'w, M: GrassMaterial>
+where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    pipeline: Res<'w, GrassMaterialPipeline<M>>,
+    pipelines: ResMut<'w, SpecializedMeshPipelines<GrassMaterialPipeline<M>>>,
+    pipeline_cache: Res<'w, PipelineCache>,
+    meshes: Res<'w, RenderAssets<Mesh>>,
+    render_mesh_instances: Res<'w, RenderMeshInstances>,
+    render_materials: Res<'w, RenderGrassMaterials<M>>,
+}
+
+impl<M: GrassMaterial> GrassSpecializer<'_, M>
+where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    fn mesh_instance(&self, entity: Entity) -> Option<&RenderMeshInstance> {
+        self.render_mesh_instances.get(&entity)
+    }
+
+    /// The pipeline drawing `entity` with `material`, `None` until its mesh and material are prepared.
+    /// The topology of the mesh is added to `mesh_key`.
+    fn specialize(
+        &mut self,
+        entity: Entity,
+        material: &Handle<M>,
+        mesh_key: MeshPipelineKey,
+        prepass: bool,
+        shading: GrassShading,
+    ) -> Option<CachedRenderPipelineId> {
+        let mesh = self.meshes.get(self.render_mesh_instances.get(&entity)?.mesh_asset_id)?;
+        let material = self.render_materials.get(&material.id())?;
+        let key = GrassMaterialPipelineKey {
+            grass_key: GrassPipelineKey {
+                mesh_key: mesh_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology),
+                prepass,
+                shading,
+            },
+            bind_group_data: material.data.clone(),
+        };
+        match self.pipelines.specialize(&self.pipeline_cache, &self.pipeline, key, &mesh.layout) {
+            Ok(pipeline) => Some(pipeline),
+            Err(err) => {
+                error!("{}", err);
+                None
+            }
+        }
+    }
+}
+
+pub(crate) fn grass_queue<M: GrassMaterial>(
     opaque_3d_draw_functions: Res<DrawFunctions<Opaque3d>>,
-    custom_pipeline: Res<GrassPipeline>,
+    mut specializer: GrassSpecializer<M>,
     msaa: Res<Msaa>,
-    mut pipelines: ResMut<SpecializedMeshPipelines<GrassPipeline>>,
-    pipeline_cache: Res<PipelineCache>,
-    meshes: Res<RenderAssets<Mesh>>,
-    render_mesh_instances: Res<RenderMeshInstances>,
-    material_meshes: Query<Entity, With<RenderGrassChunks>>,
-    mut views: Query<(&ExtractedView, &mut RenderPhase<Opaque3d>)>,
-) {
-    let draw_custom = opaque_3d_draw_functions.read().id::<DrawGrass>();
+    images: Res<RenderAssets<Image>>,
+    material_meshes: Query<(Entity, &GrassShading, &Handle<M>), With<RenderGrassChunks>>,
+    mut views: Query<(&ExtractedView, ViewKeyQuery, &mut RenderPhase<Opaque3d>)>,
+) where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    let draw_custom = opaque_3d_draw_functions.read().id::<DrawGrass<M>>();
+
+    for (view, view_key_query, mut opaque_phase) in &mut views {
+        let view_key = view_key(view, view_key_query, &msaa, &images);
+        let deferred_prepass = view_key.contains(MeshPipelineKey::DEFERRED_PREPASS);
 
-    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
-    for (view, mut opaque_phase) in &mut views {
-        let view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
         let rangefinder = view.rangefinder3d();
-        for entity in &material_meshes {
-            let Some(mesh_instance) = render_mesh_instances.get(&entity) else {
+        for (entity, shading, material_handle) in &material_meshes {
+            // PBR grass is drawn by the deferred lighting pass when the camera renders deferred.
+            if deferred_prepass && *shading == GrassShading::Pbr {
+                continue;
+            }
+            let Some(translation) = specializer.mesh_instance(entity).map(|instance| instance.transforms.transform.translation) else {
                 continue;
             };
-            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+            let Some(pipeline) = specializer.specialize(entity, material_handle, view_key, false, *shading) else {
                 continue;
             };
-            let key = view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology);
-            let pipeline = pipelines
-                .specialize(&pipeline_cache, &custom_pipeline, key, &mesh.layout)
-                .unwrap();
             opaque_phase.add(Opaque3d {
                 entity,
                 pipeline,
                 draw_function: draw_custom,
-                distance: rangefinder.distance_translation(&mesh_instance.transforms.transform.translation),
+                distance: rangefinder.distance_translation(&translation),
                 batch_range: 0..1,
                 dynamic_offset: None,
             });
         }
     }
-}
\ No newline at end of file
+}
+
+/// The views with a prepass or deferred phase, and their [`ViewKeyQuery`].
+type PrepassViewQuery = (
+    &'static ExtractedView,
+    ViewKeyQuery,
+    Option<&'static mut RenderPhase<Opaque3dPrepass>>,
+    Option<&'static mut RenderPhase<Opaque3dDeferred>>,
+);
+type PrepassViewFilter = Or<(With<RenderPhase<Opaque3dPrepass>>, With<RenderPhase<Opaque3dDeferred>>)>;
+
+pub(crate) fn grass_queue_prepass<M: GrassMaterial>(
+    opaque_draw_functions: Res<DrawFunctions<Opaque3dPrepass>>,
+    opaque_deferred_draw_functions: Res<DrawFunctions<Opaque3dDeferred>>,
+    mut specializer: GrassSpecializer<M>,
+    msaa: Res<Msaa>,
+    images: Res<RenderAssets<Image>>,
+    material_meshes: Query<(Entity, &GrassShading, &Handle<M>), With<RenderGrassChunks>>,
+    mut views: Query<PrepassViewQuery, PrepassViewFilter>,
+) where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    let draw_prepass = opaque_draw_functions.read().id::<DrawGrassPrepass<M>>();
+    let draw_deferred = opaque_deferred_draw_functions.read().id::<DrawGrassPrepass<M>>();
+
+    for (view, view_key_query, mut opaque_phase, mut opaque_deferred_phase) in &mut views {
+        // only PBR grass is written to the gbuffer, the rest goes through the regular prepass
+        let view_key = view_key(view, view_key_query, &msaa, &images);
+        let deferred_prepass = view_key.contains(MeshPipelineKey::DEFERRED_PREPASS);
+        let view_key = view_key - MeshPipelineKey::DEFERRED_PREPASS;
+
+        let rangefinder = view.rangefinder3d();
+        for (entity, shading, material_handle) in &material_meshes {
+            let Some(translation) = specializer.mesh_instance(entity).map(|instance| instance.transforms.transform.translation) else {
+                continue;
+            };
+
+            let deferred = deferred_prepass && *shading == GrassShading::Pbr;
+            let mut mesh_key = view_key;
+            if deferred {
+                mesh_key |= MeshPipelineKey::DEFERRED_PREPASS;
+            }
+            let Some(pipeline_id) = specializer.specialize(entity, material_handle, mesh_key, true, *shading) else {
+                continue;
+            };
+
+            let distance = rangefinder.distance_translation(&translation);
+            if deferred {
+                if let Some(opaque_deferred_phase) = opaque_deferred_phase.as_mut() {
+                    opaque_deferred_phase.add(Opaque3dDeferred {
+                        entity,
+                        pipeline_id,
+                        draw_function: draw_deferred,
+                        distance,
+                        batch_range: 0..1,
+                        dynamic_offset: None,
+                    });
+                }
+            } else if let Some(opaque_phase) = opaque_phase.as_mut() {
+                opaque_phase.add(Opaque3dPrepass {
+                    entity,
+                    pipeline_id,
+                    draw_function: draw_prepass,
+                    distance,
+                    batch_range: 0..1,
+                    dynamic_offset: None,
+                });
+            }
+        }
+    }
+}
+
+pub(crate) fn grass_queue_shadows<M: GrassMaterial>(
+    shadow_draw_functions: Res<DrawFunctions<Shadow>>,
+    mut specializer: GrassSpecializer<M>,
+    material_meshes: Query<(Entity, &GrassShadowCaster, &Handle<M>), With<RenderGrassChunks>>,
+    view_lights: Query<&ViewLightEntities>,
+    mut view_light_shadow_phases: Query<(&LightEntity, &mut RenderPhase<Shadow>)>,
+) where
+    M::Data: PartialEq + Eq + Hash + Clone,
+{
+    let draw_shadow = shadow_draw_functions.read().id::<DrawGrassShadow<M>>();
+
+    for view_lights in &view_lights {
+        for view_light_entity in view_lights.lights.iter().copied() {
+            let Ok((light_entity, mut shadow_phase)) = view_light_shadow_phases.get_mut(view_light_entity) else {
+                continue;
+            };
+            let is_directional_light = matches!(light_entity, LightEntity::Directional { .. });
+
+            for (entity, shadow_caster, material_handle) in &material_meshes {
+                if !shadow_caster.0 || !specializer.mesh_instance(entity).is_some_and(|instance| instance.shadow_caster) {
+                    continue;
+                }
+
+                let mut mesh_key = MeshPipelineKey::DEPTH_PREPASS;
+                if is_directional_light {
+                    mesh_key |= MeshPipelineKey::DEPTH_CLAMP_ORTHO;
+                }
+                let Some(pipeline) = specializer.specialize(entity, material_handle, mesh_key, true, GrassShading::default()) else {
+                    continue;
+                };
+
+                shadow_phase.add(Shadow {
+                    entity,
+                    pipeline,
+                    draw_function: draw_shadow,
+                    distance: 0.0,
+                    batch_range: 0..1,
+                    dynamic_offset: None,
+                });
+            }
+        }
+    }
+}
+
+/// Queues the scatters into the opaque, alpha mask or transparent phase, depending on the alpha mode of their material.
+pub(crate) fn scatter_queue(
+    opaque_draw_functions: Res<DrawFunctions<Opaque3d>>,
+    alpha_mask_draw_functions: Res<DrawFunctions<AlphaMask3d>>,
+    transparent_draw_functions: Res<DrawFunctions<Transparent3d>>,
+    scatter_pipeline: Res<ScatterPipeline>,
+    msaa: Res<Msaa>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<ScatterPipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    meshes: Res<RenderAssets<Mesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    render_materials: Res<RenderMaterials<StandardMaterial>>,
+    images: Res<RenderAssets<Image>>,
+    scatters: Query<(Entity, &Scatter), With<RenderGrassChunks>>,
+    mut views: Query<(
+        &ExtractedView,
+        ViewKeyQuery,
+        &mut RenderPhase<Opaque3d>,
+        &mut RenderPhase<AlphaMask3d>,
+        &mut RenderPhase<Transparent3d>,
+    )>,
+) {
+    let draw_opaque = opaque_draw_functions.read().id::<DrawScatter>();
+    let draw_alpha_mask = alpha_mask_draw_functions.read().id::<DrawScatter>();
+    let draw_transparent = transparent_draw_functions.read().id::<DrawScatter>();
+
+    for (view, view_key_query, mut opaque_phase, mut alpha_mask_phase, mut transparent_phase) in &mut views {
+        let view_key = view_key(view, view_key_query, &msaa, &images);
+
+        let rangefinder = view.rangefinder3d();
+        for (entity, scatter) in &scatters {
+            let Some(mesh_instance) = render_mesh_instances.get(&entity) else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+                continue;
+            };
+            let Some(material) = render_materials.get(&scatter.material.id()) else {
+                continue;
+            };
+
+            let alpha_mode = material.properties.alpha_mode;
+            let key = MaterialPipelineKey {
+                mesh_key: view_key
+                    | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology)
+                    | alpha_mode_pipeline_key(alpha_mode),
+                bind_group_data: material.key.clone(),
+            };
+            let pipeline = match pipelines.specialize(&pipeline_cache, &scatter_pipeline, key, &mesh.layout) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    error!("{}", err);
+                    continue;
+                }
+            };
+
+            let distance = rangefinder.distance_translation(&mesh_instance.transforms.transform.translation)
+                + material.properties.depth_bias;
+            match alpha_mode {
+                AlphaMode::Opaque => opaque_phase.add(Opaque3d {
+                    entity,
+                    pipeline,
+                    draw_function: draw_opaque,
+                    distance,
+                    batch_range: 0..1,
+                    dynamic_offset: None,
+                }),
+                AlphaMode::Mask(_) => alpha_mask_phase.add(AlphaMask3d {
+                    entity,
+                    pipeline,
+                    draw_function: draw_alpha_mask,
+                    distance,
+                    batch_range: 0..1,
+                    dynamic_offset: None,
+                }),
+                AlphaMode::Blend | AlphaMode::Premultiplied | AlphaMode::Add | AlphaMode::Multiply => transparent_phase.add(Transparent3d {
+                    entity,
+                    pipeline,
+                    draw_function: draw_transparent,
+                    distance,
+                    batch_range: 0..1,
+                    dynamic_offset: None,
+                }),
+            }
+        }
+    }
+}
+
+/// The parts of a camera that make up the view bits of its [`MeshPipelineKey`].
+type ViewKeyQuery = (
+    Option<&'static Tonemapping>,
+    Option<&'static DebandDither>,
+    Option<&'static EnvironmentMapLight>,
+    Option<&'static ShadowFilteringMethod>,
+    Option<&'static ScreenSpaceAmbientOcclusionSettings>,
+    Option<&'static Projection>,
+    (Has<DepthPrepass>, Has<NormalPrepass>, Has<MotionVectorPrepass>, Has<DeferredPrepass>),
+);
+
+fn view_key(
+    view: &ExtractedView,
+    (
+        tonemapping,
+        dither,
+        environment_map,
+        shadow_filter_method,
+        ssao,
+        projection,
+        (depth_prepass, normal_prepass, motion_vector_prepass, deferred_prepass),
+    ): QueryItem<ViewKeyQuery>,
+    msaa: &Msaa,
+    images: &RenderAssets<Image>,
+) -> MeshPipelineKey {
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples());
+    let mut view_key = msaa_key | MeshPipelineKey::from_hdr(view.hdr);
+
+    // The mesh view layout depends on which prepass textures the camera has.
+    if depth_prepass {
+        view_key |= MeshPipelineKey::DEPTH_PREPASS;
+    }
+    if normal_prepass {
+        view_key |= MeshPipelineKey::NORMAL_PREPASS;
+    }
+    if motion_vector_prepass {
+        view_key |= MeshPipelineKey::MOTION_VECTOR_PREPASS;
+    }
+    if deferred_prepass {
+        view_key |= MeshPipelineKey::DEFERRED_PREPASS;
+    }
+
+    // The same view bits the standard material uses, the PBR shading relies on them.
+    if environment_map.is_some_and(|map| map.is_loaded(images)) {
+        view_key |= MeshPipelineKey::ENVIRONMENT_MAP;
+    }
+    if let Some(projection) = projection {
+        view_key |= match projection {
+            Projection::Perspective(_) => MeshPipelineKey::VIEW_PROJECTION_PERSPECTIVE,
+            Projection::Orthographic(_) => MeshPipelineKey::VIEW_PROJECTION_ORTHOGRAPHIC,
+        };
+    }
+    view_key |= match shadow_filter_method.unwrap_or(&ShadowFilteringMethod::default()) {
+        ShadowFilteringMethod::Hardware2x2 => MeshPipelineKey::SHADOW_FILTER_METHOD_HARDWARE_2X2,
+        ShadowFilteringMethod::Castano13 => MeshPipelineKey::SHADOW_FILTER_METHOD_CASTANO_13,
+        ShadowFilteringMethod::Jimenez14 => MeshPipelineKey::SHADOW_FILTER_METHOD_JIMENEZ_14,
+    };
+    if !view.hdr {
+        if let Some(tonemapping) = tonemapping {
+            view_key |= MeshPipelineKey::TONEMAP_IN_SHADER | tonemapping_pipeline_key(*tonemapping);
+        }
+        if let Some(DebandDither::Enabled) = dither {
+            view_key |= MeshPipelineKey::DEBAND_DITHER;
+        }
+    }
+    if ssao.is_some() {
+        view_key |= MeshPipelineKey::SCREEN_SPACE_AMBIENT_OCCLUSION;
+    }
+
+    view_key
+}
+
+const fn tonemapping_pipeline_key(tonemapping: Tonemapping) -> MeshPipelineKey {
+    match tonemapping {
+        Tonemapping::None => MeshPipelineKey::TONEMAP_METHOD_NONE,
+        Tonemapping::Reinhard => MeshPipelineKey::TONEMAP_METHOD_REINHARD,
+        Tonemapping::ReinhardLuminance => MeshPipelineKey::TONEMAP_METHOD_REINHARD_LUMINANCE,
+        Tonemapping::AcesFitted => MeshPipelineKey::TONEMAP_METHOD_ACES_FITTED,
+        Tonemapping::AgX => MeshPipelineKey::TONEMAP_METHOD_AGX,
+        Tonemapping::SomewhatBoringDisplayTransform => MeshPipelineKey::TONEMAP_METHOD_SOMEWHAT_BORING_DISPLAY_TRANSFORM,
+        Tonemapping::TonyMcMapface => MeshPipelineKey::TONEMAP_METHOD_TONY_MC_MAPFACE,
+        Tonemapping::BlenderFilmic => MeshPipelineKey::TONEMAP_METHOD_BLENDER_FILMIC,
+    }
+}
+
+// matches the private `alpha_mode_pipeline_key` of `bevy_pbr`
+const fn alpha_mode_pipeline_key(alpha_mode: AlphaMode) -> MeshPipelineKey {
+    match alpha_mode {
+        AlphaMode::Premultiplied | AlphaMode::Add => MeshPipelineKey::BLEND_PREMULTIPLIED_ALPHA,
+        AlphaMode::Blend => MeshPipelineKey::BLEND_ALPHA,
+        AlphaMode::Multiply => MeshPipelineKey::BLEND_MULTIPLY,
+        AlphaMode::Mask(_) => MeshPipelineKey::MAY_DISCARD,
+        _ => MeshPipelineKey::NONE,
+    }
+}